@@ -0,0 +1,85 @@
+//! Benchmarks spawning short lived processes.
+//!
+//! Run with `cargo bench --bench spawn`, this compares running `true` through
+//! `mapped_command::Command` with running it through `std::process::Command`
+//! directly, including the "rebuild the whole env" approach which was used
+//! for all commands with env updates before. The `capture` group does the same for capturing
+//! the output of `echo`, which `std::process::Command::output()` reads without
+//! spawning threads.
+use criterion::{criterion_group, criterion_main, Criterion};
+use mapped_command::{Command, EnvChange, ReturnNothing, ReturnStdout};
+use std::{env, process};
+
+fn spawn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spawn");
+
+    group.bench_function("std", |b| {
+        b.iter(|| process::Command::new("true").status().unwrap())
+    });
+
+    group.bench_function("std_env_update", |b| {
+        b.iter(|| {
+            process::Command::new("true")
+                .env("MAPPED_COMMAND_BENCH", "1")
+                .status()
+                .unwrap()
+        })
+    });
+
+    group.bench_function("std_rebuilt_env", |b| {
+        b.iter(|| {
+            process::Command::new("true")
+                .env_clear()
+                .envs(env::vars_os())
+                .status()
+                .unwrap()
+        })
+    });
+
+    group.bench_function("mapped_command", |b| {
+        b.iter(|| Command::new("true", ReturnNothing).run().unwrap())
+    });
+
+    group.bench_function("mapped_command_env_update", |b| {
+        b.iter(|| {
+            Command::new("true", ReturnNothing)
+                .with_env_update("MAPPED_COMMAND_BENCH", EnvChange::Set("1".into()))
+                .run()
+                .unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+fn capture(c: &mut Criterion) {
+    let mut group = c.benchmark_group("capture");
+
+    group.bench_function("std", |b| {
+        b.iter(|| process::Command::new("echo").arg("hy").output().unwrap())
+    });
+
+    group.bench_function("mapped_command", |b| {
+        b.iter(|| {
+            Command::new("echo", ReturnStdout)
+                .with_argument("hy")
+                .run()
+                .unwrap()
+        })
+    });
+
+    group.bench_function("mapped_command_process_group", |b| {
+        b.iter(|| {
+            Command::new("echo", ReturnStdout)
+                .with_argument("hy")
+                .with_new_process_group(true)
+                .run()
+                .unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, spawn, capture);
+criterion_main!(benches);
@@ -62,6 +62,7 @@ proptest! {
                     exit_status: 0.into(),
                     stdout,
                     stderr,
+                    ..Default::default()
                 })
             })
             .run()
@@ -0,0 +1,440 @@
+//! `Display` and `Debug` implementations for [`Command`].
+use crate::{
+    env_diff::looks_like_secret_env_var, secrets::REDACTED, Command, EnvChange,
+    UnexpectedExitStatus,
+};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    env,
+    ffi::{OsStr, OsString},
+    fmt::{self, Write},
+    io,
+};
+
+/// Renders the command as shell command line, which can be copy pasted into a shell.
+///
+/// The command line consists of:
+///
+/// - `cd <dir> && ` if there is a [`Command::working_directory_override()`]
+/// - the env updates, either as `KEY=value` assignments or if variables are removed or
+///   the env is not (fully) inherited using `env -i`/`env -u KEY`, inherited variables
+///   are rendered as `KEY="$KEY"` and appended/prepended path lists as
+///   `KEY='/new/bin'"${KEY:+:$KEY}"`
+/// - the program and arguments
+///
+/// Secret arguments and env values (see [`Command::with_secret_argument()`] and
+/// [`Command::with_secret_env_update()`]) are rendered as `'***'`. All values are quoted
+/// as necessary. Values which are not valid UTF-8 or contain
+/// control characters are quoted using `$'...'`, which is supported by e.g. `bash`
+/// and `zsh`. Other settings (e.g. the output mapping, exit status checking or unix
+/// specific settings) are not rendered.
+///
+/// ```
+/// # use mapped_command::{Command, ReturnNothing};
+/// let cmd = Command::new("git", ReturnNothing)
+///     .with_arguments(["commit", "-m", "it's done"])
+///     .with_env_update("GIT_AUTHOR_NAME", "Jane Doe")
+///     .with_working_directory_override(Some("/repo"));
+/// assert_eq!(
+///     cmd.to_string(),
+///     r#"cd /repo && GIT_AUTHOR_NAME='Jane Doe' git commit -m 'it'\''s done'"#
+/// );
+/// ```
+impl<Output, Error> fmt::Display for Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    fn fmt(&self, fter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(dir) = self.working_directory_override() {
+            write!(fter, "cd {} && ", quote(dir.as_os_str()))?;
+        }
+
+        let mut env_updates = self.env_updates().iter().collect::<Vec<_>>();
+        env_updates.sort_by_key(|(key, _)| *key);
+
+        let clear_env = !self.inherit_env() || self.env_inherit_filter().is_some();
+        let use_env = clear_env
+            || env_updates
+                .iter()
+                .any(|(key, change)| **change == EnvChange::Remove || !is_shell_identifier(key));
+        if use_env {
+            fter.write_str("env ")?;
+        }
+        if clear_env {
+            fter.write_str("-i ")?;
+            if self.inherit_env() {
+                let mut inherited = env::vars_os()
+                    .map(|(key, _)| key)
+                    .filter(|key| !self.env_updates().contains_key(key))
+                    .filter(|key| self.inherited_env_var(key).is_some())
+                    .collect::<Vec<_>>();
+                inherited.sort();
+                for key in inherited {
+                    let value = env::var_os(&key);
+                    write_inherited(fter, &key, value, self.is_secret_env_var(&key))?;
+                }
+            }
+        } else {
+            for (key, change) in &env_updates {
+                if **change == EnvChange::Remove {
+                    write!(fter, "-u {} ", quote(key))?;
+                }
+            }
+        }
+
+        for (key, change) in env_updates {
+            match &*self.redacted_env_change(key, change) {
+                EnvChange::Remove => {}
+                EnvChange::Set(value) => write!(fter, "{}={} ", quote(key), quote(value))?,
+                EnvChange::Inherit => {
+                    if clear_env {
+                        let value = env::var_os(key);
+                        write_inherited(fter, key, value, self.is_secret_env_var(key))?;
+                    }
+                }
+                EnvChange::Prepend(paths) | EnvChange::Append(paths) => {
+                    write!(fter, "{}=", quote(key))?;
+                    let inherited = if self.inherited_env_var(key).is_none() {
+                        None
+                    } else if is_shell_identifier(key) {
+                        Some(key.to_string_lossy())
+                    } else {
+                        None
+                    };
+                    match (change, inherited) {
+                        (EnvChange::Prepend(_), Some(key)) => {
+                            write!(fter, "{}\"${{{key}:+:${key}}}\" ", quote(paths), key = key)?
+                        }
+                        (_, Some(key)) => {
+                            write!(fter, "\"${{{key}:+${key}:}}\"{} ", quote(paths), key = key)?
+                        }
+                        (_, None) => write!(fter, "{} ", quote(paths))?,
+                    }
+                }
+            }
+        }
+
+        write!(fter, "{}", quote(self.program()))?;
+        for argument in self.redacted_arguments() {
+            write!(fter, " {}", quote(argument))?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows all settings except the output mapping and exec replacement callback.
+///
+/// Like with `Display` secret arguments and env values are shown as `***`.
+impl<Output, Error> fmt::Debug for Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    fn fmt(&self, fter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = fter.debug_struct("Command");
+        debug
+            .field("program", &self.program)
+            .field("arguments", &self.redacted_arguments().collect::<Vec<_>>())
+            .field(
+                "env_updates",
+                &self
+                    .env_updates
+                    .iter()
+                    .map(|(key, change)| (key, self.redacted_env_change(key, change)))
+                    .collect::<BTreeMap<_, _>>(),
+            )
+            .field(
+                "working_directory_override",
+                &self.working_directory_override,
+            )
+            .field("expected_exit_status", &self.expected_exit_status)
+            .field("check_exit_status", &self.check_exit_status)
+            .field("inherit_env", &self.inherit_env)
+            .field("env_inherit_filter", &self.env_inherit_filter)
+            .field("env_diff_in_errors", &self.env_diff_in_errors)
+            .field("validate_before_spawn", &self.validate_before_spawn);
+        #[cfg(unix)]
+        debug.field("unix_settings", &self.unix_settings);
+        debug.finish_non_exhaustive()
+    }
+}
+
+/// Writes an assignment passing on the inherited `value` of `key` through `env -i`.
+///
+/// `secret` marks env variables registered as secret on the command.
+fn write_inherited(
+    fter: &mut fmt::Formatter<'_>,
+    key: &OsStr,
+    value: Option<OsString>,
+    secret: bool,
+) -> fmt::Result {
+    if is_shell_identifier(key) {
+        let key = key.to_string_lossy();
+        write!(fter, "{}=\"${}\" ", key, key)
+    } else {
+        // Can't be expanded by the shell, so use the current value.
+        let value = match value {
+            Some(_) if secret || looks_like_secret_env_var(key) => REDACTED.into(),
+            value => value.unwrap_or_default(),
+        };
+        write!(fter, "{}={} ", quote(key), quote(&value))
+    }
+}
+
+fn is_shell_identifier(name: &OsStr) -> bool {
+    let name = name.as_encoded_bytes();
+    name.first()
+        .is_some_and(|first| first.is_ascii_alphabetic() || *first == b'_')
+        && name
+            .iter()
+            .all(|byte| byte.is_ascii_alphanumeric() || *byte == b'_')
+}
+
+/// Quotes given value for a POSIX shell if necessary.
+///
+/// Values which are not valid UTF-8 or contain control characters are quoted using
+/// `$'...'`, everything else which needs quoting with `'...'`.
+pub(crate) fn quote(value: &OsStr) -> Cow<'_, str> {
+    let bytes = value.as_encoded_bytes();
+    let is_safe = |byte: &u8| byte.is_ascii_alphanumeric() || b"_@%+:,./-".contains(byte);
+    if !bytes.is_empty() && bytes.iter().all(is_safe) {
+        return Cow::Borrowed(value.to_str().expect("ascii is valid utf-8"));
+    }
+
+    match value.to_str() {
+        Some(value) if !value.chars().any(char::is_control) => {
+            Cow::Owned(format!("'{}'", value.replace('\'', r"'\''")))
+        }
+        _ => {
+            let mut quoted = String::from("$'");
+            for chunk in bytes.utf8_chunks() {
+                for char in chunk.valid().chars() {
+                    match char {
+                        '\\' => quoted.push_str(r"\\"),
+                        '\'' => quoted.push_str(r"\'"),
+                        '\n' => quoted.push_str(r"\n"),
+                        '\t' => quoted.push_str(r"\t"),
+                        '\r' => quoted.push_str(r"\r"),
+                        char if char.is_ascii_control() => {
+                            write!(quoted, "\\x{:02x}", char as u8).unwrap()
+                        }
+                        char if char.is_control() => {
+                            write!(quoted, "\\u{:04x}", char as u32).unwrap()
+                        }
+                        char => quoted.push(char),
+                    }
+                }
+                for byte in chunk.invalid() {
+                    write!(quoted, "\\x{:02x}", byte).unwrap();
+                }
+            }
+            quoted.push('\'');
+            Cow::Owned(quoted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod quote {
+        use super::super::quote;
+        use std::ffi::OsStr;
+
+        #[test]
+        fn safe_values_are_not_quoted() {
+            assert_eq!(quote(OsStr::new("/usr/bin/git")), "/usr/bin/git");
+            assert_eq!(quote(OsStr::new("--foo=bar")), "'--foo=bar'");
+            assert_eq!(quote(OsStr::new("a,b:c@d%e+f")), "a,b:c@d%e+f");
+        }
+
+        #[test]
+        fn values_are_single_quoted_if_necessary() {
+            assert_eq!(quote(OsStr::new("")), "''");
+            assert_eq!(quote(OsStr::new("a b")), "'a b'");
+            assert_eq!(quote(OsStr::new("$HOME")), "'$HOME'");
+            assert_eq!(quote(OsStr::new("it's")), r"'it'\''s'");
+            assert_eq!(quote(OsStr::new("äöü")), "'äöü'");
+        }
+
+        #[test]
+        fn control_characters_use_ansi_c_quoting() {
+            assert_eq!(quote(OsStr::new("a\nb's\t\x1b")), r"$'a\nb\'s\t\x1b'");
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn non_utf8_bytes_use_ansi_c_quoting() {
+            use std::os::unix::ffi::OsStrExt;
+            assert_eq!(quote(OsStr::from_bytes(b"a\xffb\\")), r"$'a\xffb\\'");
+        }
+    }
+
+    mod write_inherited {
+        use super::super::write_inherited;
+        use std::{ffi::OsStr, fmt};
+
+        struct Inherited(&'static str, Option<&'static str>, bool);
+
+        impl fmt::Display for Inherited {
+            fn fmt(&self, fter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let Inherited(key, value, secret) = *self;
+                write_inherited(fter, OsStr::new(key), value.map(Into::into), secret)
+            }
+        }
+
+        #[test]
+        fn shell_identifiers_are_expanded_by_the_shell() {
+            assert_eq!(
+                Inherited("HOME", Some("/root"), true).to_string(),
+                r#"HOME="$HOME" "#
+            );
+        }
+
+        #[test]
+        fn other_names_use_the_current_value() {
+            assert_eq!(
+                Inherited("MY-VAR", Some("a b"), false).to_string(),
+                "MY-VAR='a b' "
+            );
+            assert_eq!(Inherited("MY-VAR", None, false).to_string(), "MY-VAR='' ");
+        }
+
+        #[test]
+        fn secret_values_are_redacted() {
+            assert_eq!(
+                Inherited("MY-VAR", Some("hunter2"), true).to_string(),
+                "MY-VAR='***' "
+            );
+            assert_eq!(
+                Inherited("MY-TOKEN", Some("hunter2"), false).to_string(),
+                "MY-TOKEN='***' "
+            );
+        }
+    }
+
+    mod Command {
+        mod display {
+            use crate::{Command, EnvChange, ReturnNothing};
+            use std::ffi::OsStr;
+
+            #[test]
+            fn renders_program_and_arguments() {
+                let cmd = Command::new("echo", ReturnNothing).with_arguments(["a b", "c"]);
+                assert_eq!(cmd.to_string(), "echo 'a b' c");
+            }
+
+            #[test]
+            fn env_assignments_are_prefixed() {
+                let cmd = Command::new("echo", ReturnNothing)
+                    .with_env_update("B", "2 3")
+                    .with_env_update("A", "1")
+                    .with_env_update("PATH", EnvChange::Prepend("/opt/bin".into()));
+                assert_eq!(
+                    cmd.to_string(),
+                    r#"A=1 B='2 3' PATH=/opt/bin"${PATH:+:$PATH}" echo"#
+                );
+            }
+
+            #[test]
+            fn removals_use_env() {
+                let cmd = Command::new("echo", ReturnNothing)
+                    .with_env_update("FOO", EnvChange::Remove)
+                    .with_env_update("A", "1");
+                assert_eq!(cmd.to_string(), "env -u FOO A=1 echo");
+            }
+
+            #[test]
+            fn disabled_inheritance_uses_env_i() {
+                let cmd = Command::new("echo", ReturnNothing)
+                    .with_inherit_env(false)
+                    .with_env_update("HOME", EnvChange::Inherit)
+                    .with_env_update("FOO", EnvChange::Remove)
+                    .with_env_update("PATH", EnvChange::Append("/opt/bin".into()))
+                    .with_working_directory_override(Some("/my dir"));
+                assert_eq!(
+                    cmd.to_string(),
+                    r#"cd '/my dir' && env -i HOME="$HOME" PATH=/opt/bin echo"#
+                );
+            }
+
+            #[test]
+            fn inherited_secret_env_vars_are_redacted() {
+                let cmd = Command::new("echo", ReturnNothing)
+                    .with_inherit_env(false)
+                    .with_secret_env_update("MY-VAR", "hunter2")
+                    .with_env_update("MY-VAR", EnvChange::Inherit);
+                assert!(cmd.is_secret_env_var(OsStr::new("MY-VAR")));
+                assert!(!cmd.to_string().contains("hunter2"));
+            }
+
+            #[cfg(target_os = "linux")]
+            mod round_trip {
+                use crate::{Command, ReturnStdout};
+                use proptest::prelude::*;
+                use std::{
+                    ffi::OsString,
+                    os::unix::ffi::{OsStrExt, OsStringExt},
+                };
+
+                fn run_in_bash(script: &str) -> Vec<u8> {
+                    Command::new("bash", ReturnStdout)
+                        .with_arguments(["-c", script])
+                        .run()
+                        .unwrap()
+                }
+
+                proptest! {
+                    #![proptest_config(ProptestConfig::with_cases(32))]
+
+                    #[test]
+                    fn the_command_line_can_be_run_by_a_shell(
+                        arguments in proptest::collection::vec(any::<OsString>(), 0..4),
+                        value in any::<OsString>(),
+                    ) {
+                        prop_assume!(!arguments.iter().any(|arg| arg.as_bytes().contains(&0)));
+                        prop_assume!(!value.as_bytes().contains(&0));
+
+                        let cmd = Command::new("printf", ReturnStdout)
+                            .with_argument("%s\\0")
+                            .with_argument("first")
+                            .with_arguments(&arguments);
+                        let output = run_in_bash(&cmd.to_string());
+                        let got = output
+                            .split(|byte| *byte == 0)
+                            .map(|part| OsString::from_vec(part.to_vec()))
+                            .collect::<Vec<_>>();
+                        prop_assert_eq!(&got[0], "first");
+                        prop_assert_eq!(&got[1..got.len() - 1], &arguments[..]);
+
+                        let cmd = Command::new("printenv", ReturnStdout)
+                            .with_argument("MAPPED_COMMAND_VALUE")
+                            .with_env_update("MAPPED_COMMAND_VALUE", &value);
+                        let mut expected = value.into_vec();
+                        expected.push(b'\n');
+                        prop_assert_eq!(run_in_bash(&cmd.to_string()), expected);
+                    }
+                }
+            }
+        }
+
+        mod debug {
+            use crate::{Command, ReturnNothing};
+
+            #[test]
+            fn shows_the_settings() {
+                let cmd = Command::new("echo", ReturnNothing).with_argument("hy");
+                let debug = format!("{:?}", cmd);
+                assert!(
+                    debug.starts_with(r#"Command { program: "echo", arguments: ["hy"], "#),
+                    "{}",
+                    debug
+                );
+                assert!(debug.ends_with(", .. }"), "{}", debug);
+            }
+        }
+    }
+}
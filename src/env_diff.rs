@@ -0,0 +1,270 @@
+//! Diffing the env of a sub-process against the current env, see [`Command::env_diff()`].
+use crate::{secrets::REDACTED, Command, UnexpectedExitStatus};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    ffi::{OsStr, OsString},
+    fmt, io,
+};
+
+/// Parts of env variable names which mark the variable as secret (e.g. `GITHUB_TOKEN`).
+const SECRET_NAME_PARTS: &[&str] = &[
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "PRIVATE",
+    "API_KEY",
+    "APIKEY",
+    "ACCESS_KEY",
+];
+
+impl<Output, Error> Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    /// Returns the differences between the env of the current process and the env
+    /// the sub-process would have (see [`Command::create_expected_env_iter()`]).
+    ///
+    /// Values of variables which look like they contain secrets (see [`EnvDiff`]) or
+    /// were marked as secret (see [`Command::with_secret_env_update()`]) are redacted.
+    pub fn env_diff(&self) -> EnvDiff {
+        let mut parent_env = env::vars_os().collect::<BTreeMap<_, _>>();
+        let mut diff = EnvDiff::default();
+        for (key, value) in self.create_expected_env_iter() {
+            match parent_env.remove(&*key) {
+                Some(old_value) if old_value == *value => {}
+                Some(old_value) => {
+                    let new_value = self.redact_env_value(&key, value.into_owned());
+                    let old_value = self.redact_env_value(&key, old_value);
+                    diff.changed
+                        .insert(key.into_owned(), (old_value, new_value));
+                }
+                None => {
+                    let value = self.redact_env_value(&key, value.into_owned());
+                    diff.added.insert(key.into_owned(), value);
+                }
+            }
+        }
+        diff.removed = parent_env.into_keys().collect();
+        diff
+    }
+
+    fn redact_env_value(&self, key: &OsStr, value: OsString) -> OsString {
+        if self.is_secret_env_var(key) || looks_like_secret_env_var(key) {
+            REDACTED.into()
+        } else {
+            value
+        }
+    }
+
+    /// Returns true if [`UnexpectedExitStatus`] errors include the [`Command::env_diff()`].
+    ///
+    /// By default this is `false`.
+    pub fn env_diff_in_errors(&self) -> bool {
+        self.env_diff_in_errors
+    }
+
+    /// Returns this command with including the env diff in errors enabled or disabled.
+    ///
+    /// If enabled the [`Command::env_diff()`] is computed before running the command and
+    /// included in the [`UnexpectedExitStatus`] error if the exit status check fails,
+    /// see [`UnexpectedExitStatus::env_diff()`].
+    pub fn with_env_diff_in_errors(mut self, include: bool) -> Self {
+        self.env_diff_in_errors = include;
+        self
+    }
+}
+
+/// The differences between the env of the current process and the env of a sub-process.
+///
+/// Values of variables whose name contains e.g. `TOKEN`, `SECRET`, `PASSWORD` or ends
+/// with `_KEY` (ignoring the case) are replaced with `***`.
+///
+/// The `Display` implementation lists one variable per line, prefixed with `+` for added,
+/// `-` for removed and `~` for changed variables.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EnvDiff {
+    added: BTreeMap<OsString, OsString>,
+    removed: BTreeSet<OsString>,
+    changed: BTreeMap<OsString, (OsString, OsString)>,
+}
+
+impl EnvDiff {
+    /// Variables not in the current env, with their (possibly redacted) value.
+    pub fn added(&self) -> &BTreeMap<OsString, OsString> {
+        &self.added
+    }
+
+    /// Variables in the current env which the sub-process won't have.
+    pub fn removed(&self) -> &BTreeSet<OsString> {
+        &self.removed
+    }
+
+    /// Variables with a different value, with their old and new (possibly redacted) value.
+    pub fn changed(&self) -> &BTreeMap<OsString, (OsString, OsString)> {
+        &self.changed
+    }
+
+    /// Returns true if the env of the sub-process is the same as the current env.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for EnvDiff {
+    fn fmt(&self, fter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.added {
+            writeln!(
+                fter,
+                "+{}={}",
+                key.to_string_lossy(),
+                value.to_string_lossy()
+            )?;
+        }
+        for key in &self.removed {
+            writeln!(fter, "-{}", key.to_string_lossy())?;
+        }
+        for (key, (old_value, new_value)) in &self.changed {
+            writeln!(
+                fter,
+                "~{}={} -> {}",
+                key.to_string_lossy(),
+                old_value.to_string_lossy(),
+                new_value.to_string_lossy()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the env variable with given name likely contains a secret.
+pub(crate) fn looks_like_secret_env_var(name: &OsStr) -> bool {
+    let name = name.to_string_lossy().to_ascii_uppercase();
+    name.ends_with("_KEY") || SECRET_NAME_PARTS.iter().any(|part| name.contains(part))
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod EnvDiff {
+        use crate::{Command, EnvChange, ExecResult, ReturnNothing};
+        use std::{env, ffi::OsString};
+
+        #[test]
+        fn an_unchanged_env_has_an_empty_diff() {
+            let cmd = Command::new("foo", ReturnNothing);
+            assert!(cmd.env_diff().is_empty());
+            assert_eq!(cmd.env_diff().to_string(), "");
+        }
+
+        #[test]
+        fn added_removed_and_changed_variables_are_listed() {
+            let (removed, _) = env::vars_os().next().unwrap();
+            let (changed, old_value) = env::vars_os().nth(1).unwrap();
+            let cmd = Command::new("foo", ReturnNothing)
+                .with_env_update(&removed, EnvChange::Remove)
+                .with_env_update(&changed, "new value")
+                .with_env_update("MAPPED_COMMAND_ADDED", "added");
+
+            let diff = cmd.env_diff();
+
+            assert_eq!(diff.removed().iter().collect::<Vec<_>>(), vec![&removed]);
+            assert_eq!(
+                diff.added().get(&OsString::from("MAPPED_COMMAND_ADDED")),
+                Some(&"added".into())
+            );
+            assert_eq!(diff.added().len(), 1);
+            if super::super::looks_like_secret_env_var(&changed) {
+                assert_eq!(
+                    diff.changed().get(&changed),
+                    Some(&("***".into(), "***".into()))
+                );
+            } else {
+                assert_eq!(
+                    diff.changed().get(&changed),
+                    Some(&(old_value, "new value".into()))
+                );
+            }
+            assert_eq!(diff.changed().len(), 1);
+        }
+
+        #[test]
+        fn secret_values_are_redacted() {
+            let cmd = Command::new("foo", ReturnNothing)
+                .with_inherit_env(false)
+                .with_env_update("GITHUB_TOKEN", "ghp_1234")
+                .with_env_update("aws_secret_access_key", "abcd")
+                .with_env_update("SSH_KEY", "xyz")
+                .with_env_update("KEYBOARD", "us");
+
+            assert_eq!(
+                cmd.env_diff()
+                    .to_string()
+                    .lines()
+                    .take(4)
+                    .collect::<Vec<_>>(),
+                vec![
+                    "+GITHUB_TOKEN=***",
+                    "+KEYBOARD=us",
+                    "+SSH_KEY=***",
+                    "+aws_secret_access_key=***",
+                ]
+            );
+        }
+
+        #[test]
+        fn the_diff_can_be_included_in_errors() {
+            let cmd = Command::new("foo", ReturnNothing)
+                .with_env_update("MAPPED_COMMAND_ADDED", "added")
+                .with_exec_replacement_callback(|_, _| {
+                    Ok(ExecResult {
+                        exit_status: 1.into(),
+                        ..Default::default()
+                    })
+                });
+            assert!(!cmd.env_diff_in_errors());
+            let cmd = cmd.with_env_diff_in_errors(true);
+            assert!(cmd.env_diff_in_errors());
+
+            let err = match cmd.run() {
+                Err(crate::CommandExecutionError::UnexpectedExitStatus(err)) => err,
+                res => panic!("unexpected result: {:?}", res),
+            };
+            assert_eq!(err.got(), 1);
+            assert_eq!(err.expected(), 0);
+            assert!(err
+                .env_diff()
+                .unwrap()
+                .added()
+                .contains_key(&OsString::from("MAPPED_COMMAND_ADDED")));
+            assert!(err.to_string().contains("+MAPPED_COMMAND_ADDED=added\n"));
+        }
+
+        #[test]
+        fn by_default_errors_do_not_include_the_diff() {
+            let res = Command::new("foo", ReturnNothing)
+                .with_exec_replacement_callback(|_, _| {
+                    Ok(ExecResult {
+                        exit_status: 1.into(),
+                        ..Default::default()
+                    })
+                })
+                .run();
+
+            match res {
+                Err(crate::CommandExecutionError::UnexpectedExitStatus(err)) => {
+                    assert!(err.env_diff().is_none());
+                    assert_eq!(
+                        err.to_string(),
+                        "Unexpected exit status. Got: 0x1, Expected: 0x0"
+                    );
+                }
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+    }
+}
@@ -0,0 +1,518 @@
+//! Parsing of dotenv files, see [`Command::with_env_file()`].
+use crate::{Command, EnvChange, UnexpectedExitStatus};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs, io,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::Chars,
+};
+use thiserror::Error;
+
+impl<Output, Error> Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    /// Returns this command with the env variables from given dotenv file added as env updates.
+    ///
+    /// Each variable is added as [`EnvChange::Set`], replacing existing updates for the
+    /// same variable. Supported syntax:
+    ///
+    /// - `KEY=value` lines, optionally prefixed with `export `, empty lines and `#` comments
+    /// - unquoted values are trimmed and end at a ` #` comment
+    /// - single quoted values are taken literally
+    /// - double quoted values support the escapes `\n`, `\r`, `\t`, `\\`, `\"` and `\$`
+    /// - quoted values can span multiple lines
+    /// - `${VAR}`, `$VAR` and `${VAR:-default}` in unquoted and double quoted values are
+    ///   replaced with the value of `VAR`, which is looked up in the variables defined
+    ///   earlier in the file and then in the env the sub-process would have (see
+    ///   [`Command::create_expected_env_iter()`]). Undefined variables are replaced with
+    ///   an empty string (or the default).
+    ///
+    /// Fails if the file can't be read or has invalid syntax, parse errors contain the
+    /// line and column of the problem.
+    pub fn with_env_file(self, path: impl AsRef<Path>) -> Result<Self, EnvFileError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|source| EnvFileError::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        let env = self
+            .create_expected_env_iter()
+            .filter_map(|(key, value)| Some((key.into_owned().into_string().ok()?, value)))
+            .filter_map(|(key, value)| Some((key, value.into_owned().into_string().ok()?)))
+            .collect::<HashMap<_, _>>();
+
+        let variables =
+            parse(&content, |name| env.get(name).cloned()).map_err(|err| EnvFileError::Parse {
+                path: path.to_owned(),
+                line: err.line,
+                column: err.column,
+                reason: err.reason,
+            })?;
+
+        Ok(self.with_env_updates(
+            variables
+                .into_iter()
+                .map(|(key, value)| (OsString::from(key), EnvChange::Set(value.into()))),
+        ))
+    }
+}
+
+/// Loading a dotenv file failed, see [`Command::with_env_file()`].
+#[derive(Debug, Error)]
+pub enum EnvFileError {
+    /// Reading the file failed.
+    #[error("Reading env file {path:?} failed: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The file has invalid syntax.
+    ///
+    /// Line and column start at 1.
+    #[error("Invalid env file {path:?} (line {line}, column {column}): {reason}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        reason: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct ParseError {
+    line: usize,
+    column: usize,
+    reason: String,
+}
+
+/// Parses the content of a dotenv file, returning the variables in order of appearance.
+///
+/// `lookup_env` is used to interpolate variables not defined in the file itself.
+pub(crate) fn parse(
+    content: &str,
+    lookup_env: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(String, String)>, ParseError> {
+    let mut parser = Parser {
+        chars: content.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+    let mut variables: Vec<(String, String)> = Vec::new();
+    let lookup = |variables: &[(String, String)], name: &str| {
+        variables
+            .iter()
+            .rev()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| lookup_env(name))
+    };
+
+    loop {
+        parser.skip_inline_whitespace();
+        match parser.peek() {
+            None => break,
+            Some('\n') => {
+                parser.next();
+                continue;
+            }
+            Some('#') => {
+                parser.skip_line();
+                continue;
+            }
+            Some(_) => {}
+        }
+
+        let (line, column) = (parser.line, parser.column);
+        let mut key = parser.take_while(is_key_char);
+        if key == "export" && parser.peek().is_some_and(is_inline_whitespace) {
+            parser.skip_inline_whitespace();
+            key = parser.take_while(is_key_char);
+        }
+        if key.is_empty() || key.starts_with(|c: char| c.is_ascii_digit()) {
+            let reason = match parser.peek() {
+                Some(char) if key.is_empty() => format!("expected variable name, found {:?}", char),
+                _ => format!("invalid variable name {:?}", key),
+            };
+            return Err(ParseError {
+                line,
+                column,
+                reason,
+            });
+        }
+
+        parser.skip_inline_whitespace();
+        if parser.peek() != Some('=') {
+            return Err(parser.error(format!("expected '=' after variable name {:?}", key)));
+        }
+        parser.next();
+        parser.skip_inline_whitespace();
+
+        let value = match parser.peek() {
+            Some('\'') => parser.parse_single_quoted()?,
+            Some('"') => parser.parse_double_quoted(|name| lookup(&variables, name))?,
+            _ => parser.parse_unquoted(|name| lookup(&variables, name))?,
+        };
+
+        parser.skip_inline_whitespace();
+        match parser.peek() {
+            None | Some('\n') => {}
+            Some('#') => parser.skip_line(),
+            Some(char) => {
+                return Err(parser.error(format!("unexpected {:?} after value of {:?}", char, key)))
+            }
+        }
+
+        variables.push((key, value));
+    }
+
+    Ok(variables)
+}
+
+fn is_key_char(char: char) -> bool {
+    char.is_ascii_alphanumeric() || char == '_' || char == '.'
+}
+
+fn is_inline_whitespace(char: char) -> bool {
+    char != '\n' && char.is_whitespace()
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let char = self.chars.next()?;
+        if char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(char)
+    }
+
+    fn error(&self, reason: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            reason,
+        }
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(char) = self.peek().filter(|&char| predicate(char)) {
+            self.next();
+            taken.push(char);
+        }
+        taken
+    }
+
+    fn skip_inline_whitespace(&mut self) {
+        self.take_while(is_inline_whitespace);
+    }
+
+    fn skip_line(&mut self) {
+        self.take_while(|char| char != '\n');
+    }
+
+    fn parse_single_quoted(&mut self) -> Result<String, ParseError> {
+        let start = self.error("unterminated single quoted value".into());
+        self.next();
+        let value = self.take_while(|char| char != '\'');
+        if self.next().is_none() {
+            return Err(start);
+        }
+        Ok(value)
+    }
+
+    fn parse_double_quoted(
+        &mut self,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<String, ParseError> {
+        let start = self.error("unterminated double quoted value".into());
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(start),
+                Some('"') => {
+                    self.next();
+                    return Ok(value);
+                }
+                Some('\\') => {
+                    self.next();
+                    match self.next() {
+                        None => return Err(start),
+                        Some('n') => value.push('\n'),
+                        Some('r') => value.push('\r'),
+                        Some('t') => value.push('\t'),
+                        Some(char @ ('\\' | '"' | '$')) => value.push(char),
+                        // Unknown escapes are kept as is.
+                        Some(char) => {
+                            value.push('\\');
+                            value.push(char);
+                        }
+                    }
+                }
+                Some('$') => self.parse_interpolation(&mut value, &lookup)?,
+                Some(char) => {
+                    self.next();
+                    value.push(char);
+                }
+            }
+        }
+    }
+
+    fn parse_unquoted(
+        &mut self,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<String, ParseError> {
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None | Some('\n') => break,
+                Some('#') if value.ends_with(is_inline_whitespace) => break,
+                Some(char @ ('"' | '\'')) => {
+                    return Err(self.error(format!(
+                        "unexpected {:?} in unquoted value, quote the whole value instead",
+                        char
+                    )))
+                }
+                Some('\\') => {
+                    self.next();
+                    if let Some(char) = self.peek().filter(|&char| char != '\n') {
+                        self.next();
+                        value.push(char);
+                    } else {
+                        value.push('\\');
+                    }
+                }
+                Some('$') => self.parse_interpolation(&mut value, &lookup)?,
+                Some(char) => {
+                    self.next();
+                    value.push(char);
+                }
+            }
+        }
+        Ok(value.trim_end().to_owned())
+    }
+
+    /// Parses `$VAR`, `${VAR}` or `${VAR:-default}` and pushes the value to `value`.
+    fn parse_interpolation(
+        &mut self,
+        value: &mut String,
+        lookup: &impl Fn(&str) -> Option<String>,
+    ) -> Result<(), ParseError> {
+        let start = self.error("unterminated '${'".into());
+        self.next();
+        if self.peek() != Some('{') {
+            let name = self.take_while(|char| char.is_ascii_alphanumeric() || char == '_');
+            if name.is_empty() {
+                // A lone `$` is kept as is.
+                value.push('$');
+            } else {
+                value.push_str(&lookup(&name).unwrap_or_default());
+            }
+            return Ok(());
+        }
+        self.next();
+
+        let name = self.take_while(|char| char.is_ascii_alphanumeric() || char == '_');
+        if name.is_empty() {
+            return Err(self.error("expected variable name after '${'".into()));
+        }
+        let default = match self.peek() {
+            Some('}') => None,
+            Some(':') => {
+                self.next();
+                if self.next() != Some('-') {
+                    return Err(self.error("expected '-' after ':' in '${...}'".into()));
+                }
+                Some(self.take_while(|char| char != '}' && char != '\n'))
+            }
+            Some('\n') | None => return Err(start),
+            Some(char) => {
+                return Err(self.error(format!("unexpected {:?} in '${{...}}'", char)));
+            }
+        };
+        if self.next() != Some('}') {
+            return Err(start);
+        }
+
+        let resolved = lookup(&name).filter(|val| default.is_none() || !val.is_empty());
+        value.push_str(&resolved.or(default).unwrap_or_default());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod parse {
+        use super::super::{parse, ParseError};
+
+        fn parse_ok(content: &str) -> Vec<(String, String)> {
+            let env = |name: &str| match name {
+                "HOME" => Some("/home/user".to_owned()),
+                "EMPTY" => Some(String::new()),
+                _ => None,
+            };
+            parse(content, env).unwrap()
+        }
+
+        fn parse_err(content: &str) -> (usize, usize) {
+            let ParseError { line, column, .. } = parse(content, |_| None).unwrap_err();
+            (line, column)
+        }
+
+        fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        }
+
+        #[test]
+        fn parses_simple_assignments_and_comments() {
+            let content = "# comment\n\nFOO=bar\nexport BAZ = qux  # trailing\nEMPTY=\nURL=a#b\n";
+            assert_eq!(
+                parse_ok(content),
+                vars(&[
+                    ("FOO", "bar"),
+                    ("BAZ", "qux"),
+                    ("EMPTY", ""),
+                    ("URL", "a#b")
+                ])
+            );
+        }
+
+        #[test]
+        fn parses_quoted_values() {
+            let content =
+                "A='lit $HOME \\n'\nB=\"esc \\\"q\\\" \\$HOME\\t\\n\"\nC=\"multi\nline\" # c\n";
+            assert_eq!(
+                parse_ok(content),
+                vars(&[
+                    ("A", "lit $HOME \\n"),
+                    ("B", "esc \"q\" $HOME\t\n"),
+                    ("C", "multi\nline")
+                ])
+            );
+        }
+
+        #[test]
+        fn interpolates_variables() {
+            let content = "DIR=${HOME}/x\nSUB=\"$DIR/y\"\nDEF=${MISSING:-fallback}\nEMPTY_DEF=${EMPTY:-d}\nNONE=${MISSING}|$ \n";
+            assert_eq!(
+                parse_ok(content),
+                vars(&[
+                    ("DIR", "/home/user/x"),
+                    ("SUB", "/home/user/x/y"),
+                    ("DEF", "fallback"),
+                    ("EMPTY_DEF", "d"),
+                    ("NONE", "|$")
+                ])
+            );
+        }
+
+        #[test]
+        fn errors_contain_line_and_column() {
+            assert_eq!(parse_err("A=1\nNO_EQUALS\n"), (2, 10));
+            assert_eq!(parse_err("A=1\n  =2\n"), (2, 3));
+            assert_eq!(parse_err("1A=2"), (1, 1));
+            assert_eq!(parse_err("A=1\nB=\"open\n\n"), (2, 3));
+            assert_eq!(parse_err("A='open"), (1, 3));
+            assert_eq!(parse_err("A=\"x\"y"), (1, 6));
+            assert_eq!(parse_err("A=${B"), (1, 3));
+            assert_eq!(parse_err("A=${}"), (1, 5));
+            assert_eq!(parse_err("A=x'y'"), (1, 4));
+        }
+    }
+
+    mod Command {
+        mod with_env_file {
+            use crate::{Command, EnvChange, EnvFileError, ReturnNothing};
+            use std::{ffi::OsStr, fs, path::PathBuf};
+
+            fn env_file(name: &str, content: &str) -> PathBuf {
+                let path = std::env::temp_dir().join(format!(
+                    "mapped-command-{}-{}.env",
+                    std::process::id(),
+                    name
+                ));
+                fs::write(&path, content).unwrap();
+                path
+            }
+
+            #[test]
+            fn adds_the_variables_as_env_updates() {
+                let path = env_file("valid", "FOO=bar\nBAR=${FOO}-${PREV}\n");
+                let cmd = Command::new("foo", ReturnNothing)
+                    .with_inherit_env(false)
+                    .with_env_update("PREV", "prev")
+                    .with_env_file(&path)
+                    .unwrap();
+                fs::remove_file(path).unwrap();
+
+                let updates = cmd.env_updates();
+                assert_eq!(
+                    updates.get(OsStr::new("FOO")),
+                    Some(&EnvChange::Set("bar".into()))
+                );
+                assert_eq!(
+                    updates.get(OsStr::new("BAR")),
+                    Some(&EnvChange::Set("bar-prev".into()))
+                );
+            }
+
+            #[test]
+            fn parse_errors_contain_the_path_and_position() {
+                let path = env_file("invalid", "FOO=bar\nBAR\n");
+                let err = Command::new("foo", ReturnNothing)
+                    .with_env_file(&path)
+                    .err()
+                    .unwrap();
+                fs::remove_file(&path).unwrap();
+
+                match &err {
+                    EnvFileError::Parse {
+                        path: err_path,
+                        line,
+                        column,
+                        ..
+                    } => {
+                        assert_eq!(err_path, &path);
+                        assert_eq!((*line, *column), (2, 4));
+                    }
+                    other => panic!("unexpected error: {:?}", other),
+                }
+                assert!(err.to_string().contains("line 2, column 4"));
+            }
+
+            #[test]
+            fn missing_files_produce_io_errors() {
+                let err = Command::new("foo", ReturnNothing)
+                    .with_env_file("/this/does/not/exist.env")
+                    .err()
+                    .unwrap();
+                assert!(matches!(err, EnvFileError::Io { .. }));
+            }
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Patterns selecting inherited env variables, see [`EnvPattern`] and
+//! [`Command::with_env_inherit_filter()`].
+//!
+//! [`Command::with_env_inherit_filter()`]: crate::Command::with_env_inherit_filter
+use regex::Regex;
+use std::{
+    ffi::{OsStr, OsString},
+    str::Chars,
+};
+
+/// A pattern matching env variable names, see [`Command::with_env_inherit_filter()`].
+///
+/// Patterns can be created from strings:
+///
+/// - a name ending in a single `*` (and no other glob characters) becomes a
+///   [`EnvPattern::Prefix`], e.g. `"LC_*"`,
+/// - a name containing `*`, `?` or `[` is a glob (see [`EnvPattern::glob()`]), e.g. `"*_PROXY"`,
+/// - anything else is a [`EnvPattern::Exact`] name, e.g. `"PATH"`.
+///
+/// A `Regex` can be converted into a [`EnvPattern::Regex`].
+///
+/// [`Command::with_env_inherit_filter()`]: crate::Command::with_env_inherit_filter
+#[derive(Debug, Clone)]
+pub enum EnvPattern {
+    /// Matches exactly the given name.
+    Exact(OsString),
+
+    /// Matches all names starting with given prefix.
+    Prefix(OsString),
+
+    /// Matches all names matched by the regex.
+    ///
+    /// The regex is not implicitly anchored, use `^...$` to match the whole name.
+    /// Names which are not valid UTF-8 never match.
+    Regex(Regex),
+}
+
+impl EnvPattern {
+    /// Creates a pattern from a glob.
+    ///
+    /// `*` matches any number of characters, `?` matches a single character and
+    /// `[...]` matches a single character from the set (`[!...]` negates the set).
+    /// The glob always has to match the whole name.
+    ///
+    /// Like in POSIX globs a `]` directly after the opening `[` (or `[!`) is part of
+    /// the set, e.g. `[]a]`, and a `[` without closing `]` matches itself. Ranges
+    /// like `[a-z]` whose start is after their end match nothing.
+    pub fn glob(glob: &str) -> Self {
+        let mut regex = String::from("^");
+        let mut chars = glob.chars();
+        while let Some(char) = chars.next() {
+            match char {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                '[' => match parse_glob_set(chars.clone()) {
+                    Some((set, rest)) => {
+                        regex.push_str(&set);
+                        chars = rest;
+                    }
+                    None => regex.push_str(r"\["),
+                },
+                char => regex.push_str(&regex::escape(char.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        regex.push('$');
+        EnvPattern::Regex(Regex::new(&regex).expect("globs are always converted to valid regexes"))
+    }
+
+    /// Returns true if given env variable name matches this pattern.
+    pub fn matches(&self, name: &OsStr) -> bool {
+        match self {
+            EnvPattern::Exact(exact) => name == exact,
+            EnvPattern::Prefix(prefix) => name
+                .as_encoded_bytes()
+                .starts_with(prefix.as_encoded_bytes()),
+            EnvPattern::Regex(regex) => name.to_str().is_some_and(|name| regex.is_match(name)),
+        }
+    }
+}
+
+/// Converts the set of a glob following the `[` into a regex.
+///
+/// Returns `None` if the set is not terminated, else the regex and the glob after the set.
+fn parse_glob_set(mut chars: Chars<'_>) -> Option<(String, Chars<'_>)> {
+    let mut lookahead = chars.clone();
+    let negated = lookahead.next() == Some('!');
+    if negated {
+        chars = lookahead;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let start = chars.next()?;
+        if start == ']' && !first {
+            break;
+        }
+        first = false;
+        let mut lookahead = chars.clone();
+        match (lookahead.next(), lookahead.next()) {
+            (Some('-'), Some(end)) if end != ']' => {
+                ranges.push((start, end));
+                chars = lookahead;
+            }
+            _ => ranges.push((start, start)),
+        }
+    }
+
+    let mut set = String::from(if negated { "[^" } else { "[" });
+    for (start, end) in ranges.into_iter().filter(|(start, end)| start <= end) {
+        set.push_str(&regex::escape(start.encode_utf8(&mut [0; 4])));
+        if start != end {
+            set.push('-');
+            set.push_str(&regex::escape(end.encode_utf8(&mut [0; 4])));
+        }
+    }
+    if set.len() == 2 && negated {
+        // Only reversed ranges, so every character is in the negated set.
+        set = String::from("(?s:.)");
+    } else if set.len() == 1 {
+        // Only reversed ranges, so the set matches nothing.
+        set = String::from(r"[^\s\S]");
+    } else {
+        set.push(']');
+    }
+    Some((set, chars))
+}
+
+impl From<&str> for EnvPattern {
+    fn from(pattern: &str) -> Self {
+        const GLOB_CHARS: &[char] = &['*', '?', '['];
+        match pattern.strip_suffix('*') {
+            Some(prefix) if !prefix.contains(GLOB_CHARS) => EnvPattern::Prefix(prefix.into()),
+            _ if pattern.contains(GLOB_CHARS) => EnvPattern::glob(pattern),
+            _ => EnvPattern::Exact(pattern.into()),
+        }
+    }
+}
+
+impl From<String> for EnvPattern {
+    fn from(pattern: String) -> Self {
+        pattern.as_str().into()
+    }
+}
+
+impl From<OsString> for EnvPattern {
+    /// Always creates a [`EnvPattern::Exact`] pattern.
+    fn from(name: OsString) -> Self {
+        EnvPattern::Exact(name)
+    }
+}
+
+impl From<Regex> for EnvPattern {
+    fn from(regex: Regex) -> Self {
+        EnvPattern::Regex(regex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod EnvPattern {
+        use crate::EnvPattern;
+        use proptest::prelude::*;
+        use regex::Regex;
+        use std::ffi::{OsStr, OsString};
+
+        #[test]
+        fn strings_are_parsed_as_exact_prefix_or_glob() {
+            assert!(matches!(EnvPattern::from("PATH"), EnvPattern::Exact(name) if name == "PATH"));
+            assert!(
+                matches!(EnvPattern::from("LC_*"), EnvPattern::Prefix(prefix) if prefix == "LC_")
+            );
+            assert!(matches!(EnvPattern::from("*_PROXY"), EnvPattern::Regex(_)));
+        }
+
+        #[test]
+        fn globs_match_the_whole_name() {
+            let pattern = EnvPattern::from("*_PROXY");
+            assert!(pattern.matches(OsStr::new("HTTP_PROXY")));
+            assert!(!pattern.matches(OsStr::new("HTTP_PROXY_USER")));
+
+            let pattern = EnvPattern::glob("CARGO_?[!0-9]*.x");
+            assert!(pattern.matches(OsStr::new("CARGO_Ab.x")));
+            assert!(!pattern.matches(OsStr::new("CARGO_A1.x")));
+            assert!(!pattern.matches(OsStr::new("CARGO_Abx")));
+        }
+
+        #[test]
+        fn glob_sets_follow_posix_rules() {
+            let matches = |glob: &str, name: &str| EnvPattern::glob(glob).matches(OsStr::new(name));
+
+            assert!(matches("FOO[", "FOO["));
+            assert!(!matches("FOO[", "FOO"));
+            assert!(matches("[]", "[]"));
+            assert!(matches("X[!", "X[!"));
+            assert!(matches("[]abc]", "]"));
+            assert!(matches("[]abc]", "b"));
+            assert!(!matches("[]abc]", "d"));
+            assert!(!matches("[!]abc]", "]"));
+            assert!(matches("[!]abc]", "d"));
+            assert!(matches("[a-c-]", "-"));
+            assert!(matches("[a-]", "-"));
+            assert!(matches("[^]", "^"));
+            assert!(!matches("[z-a]", "z"));
+            assert!(!matches("[z-a]", "a"));
+            assert!(matches("[!z-a]", "a"));
+            assert!(matches("[z-ab]", "b"));
+            assert!(matches("[.*\\]", "\\"));
+            assert!(!matches("[.*]", "x"));
+        }
+
+        #[test]
+        fn strings_with_unterminated_sets_are_literal_globs() {
+            let pattern = EnvPattern::from("X[");
+            assert!(pattern.matches(OsStr::new("X[")));
+            let pattern = EnvPattern::from("X[*");
+            assert!(pattern.matches(OsStr::new("X[abc")));
+        }
+
+        #[test]
+        fn regex_patterns_are_not_anchored() {
+            let pattern = EnvPattern::from(Regex::new("TOKEN").unwrap());
+            assert!(pattern.matches(OsStr::new("GITHUB_TOKEN_X")));
+            assert!(!pattern.matches(OsStr::new("PATH")));
+        }
+
+        proptest! {
+            #[test]
+            fn any_glob_can_be_converted(glob in "[a-z!\\-\\[\\]^*?\\\\]{0,12}") {
+                EnvPattern::glob(&glob);
+            }
+
+            #[test]
+            fn exact_and_prefix_patterns_match_non_utf8_names(
+                prefix in any::<OsString>(),
+                suffix in any::<OsString>(),
+            ) {
+                let mut name = prefix.clone();
+                name.push(&suffix);
+                prop_assert!(EnvPattern::Prefix(prefix).matches(&name));
+                prop_assert!(EnvPattern::Exact(name.clone()).matches(&name));
+            }
+        }
+    }
+}
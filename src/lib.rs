@@ -18,7 +18,7 @@
 //!
 //! - besides allowing to decide weather the sub-process should inherit the environment and
 //!   which variables get removed/set/overwritten this type also allows you to whitelist which
-//!   env variables should be inherited.
+//!   env variables should be inherited, by exact name, prefix (e.g. `LC_*`), glob or regex.
 //!
 //! - do not have `&mut self` pass through based API. This makes it more bothersome to create
 //!   functions which create and return commands, which this types intents to make simple so
@@ -107,24 +107,87 @@
 //! # }
 //! ```
 //!
+//! # Optional Features
+//!
+//! - `serde`: Implements `Serialize`/`Deserialize` for [`ExitStatus`], [`OpaqueOsExitStatus`],
+//!   [`ExecResult`] and [`EnvChange`], e.g. to store mock fixtures or to send results to
+//!   another process.
+//!
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    collections::{BTreeSet, HashMap, HashSet},
     env::{self, VarsOs},
     ffi::{OsStr, OsString},
     fmt,
     fmt::Display,
     io,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 use thiserror::Error;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+pub use self::env_diff::EnvDiff;
+pub use self::env_file::EnvFileError;
+pub use self::env_pattern::EnvPattern;
+pub use self::program_override::{
+    program_override, remove_program_override, set_program_override, PROGRAM_OVERRIDE_ENV_PREFIX,
+};
+pub use self::program_resolution::ProgramNotFound;
 pub use self::return_settings::*;
+#[cfg(target_os = "linux")]
+pub use self::session::{ExpectMatch, Session, SessionError, SessionScript};
+pub use self::shell_words::ShellWordsError;
+#[cfg(target_os = "linux")]
+pub use self::source_script::{source_script_env, SourceScriptError};
+#[cfg(target_os = "linux")]
+pub use self::unix::PtySize;
+#[cfg(unix)]
+pub use self::unix::{InheritedFd, PreExecHook, Resource, ResourceLimit, SwitchCredentialsError};
+pub use self::validation::{ValidationError, ValidationProblem};
+pub use self::version_probe::{
+    clear_version_cache, InvalidVersionRequirement, Version, VersionProbe, VersionProbeError,
+    VersionRequirement, DEFAULT_VERSION_REGEX,
+};
 
 #[macro_use]
 mod utils;
+mod display;
+mod env_diff;
+mod env_file;
+mod env_pattern;
+#[cfg(target_os = "linux")]
+mod pidfd;
+mod program_override;
+mod program_resolution;
+#[cfg(target_os = "linux")]
+mod pty;
 mod return_settings;
+mod secrets;
+#[cfg(target_os = "linux")]
+mod session;
+mod shell_words;
+#[cfg(unix)]
+mod signal_forwarding;
+#[cfg(target_os = "linux")]
+mod source_script;
 mod sys;
+#[cfg(unix)]
+mod unix;
+mod validation;
+mod version_probe;
+
+/// The env variables inherited by [`Command::with_hermetic_env()`].
+#[cfg(not(windows))]
+pub const HERMETIC_ENV_VARS: &[&str] = &["PATH", "HOME", "TMPDIR"];
+
+/// The env variables inherited by [`Command::with_hermetic_env()`].
+///
+/// Many programs fail to start on windows if `SYSTEMROOT` is not set.
+#[cfg(windows)]
+pub const HERMETIC_ENV_VARS: &[&str] = &["PATH", "SYSTEMROOT", "TEMP", "TMP", "USERPROFILE"];
 
 /// A alternative to `std::process::Command` see module level documentation.
 pub struct Command<Output, Error>
@@ -139,17 +202,26 @@ where
     expected_exit_status: ExitStatus,
     check_exit_status: bool,
     inherit_env: bool,
+    env_inherit_filter: Option<Vec<EnvPattern>>,
+    env_diff_in_errors: bool,
+    validate_before_spawn: bool,
+    secret_arguments: BTreeSet<usize>,
+    secret_env_vars: HashSet<OsString>,
+    #[cfg(unix)]
+    unix_settings: unix::UnixSettings,
     return_settings: Option<Box<dyn OutputMapping<Output = Output, Error = Error>>>,
-    run_callback: Option<
-        Box<
-            dyn FnOnce(
-                Self,
-                &dyn OutputMapping<Output = Output, Error = Error>,
-            ) -> Result<ExecResult, io::Error>,
-        >,
-    >,
+    run_callback: Option<ExecReplacementCallback<Output, Error>>,
+    /// True if `run_callback` was replaced using `with_exec_replacement_callback`.
+    run_callback_replaced: bool,
 }
 
+type ExecReplacementCallback<Output, Error> = Box<
+    dyn FnOnce(
+        Command<Output, Error>,
+        &dyn OutputMapping<Output = Output, Error = Error>,
+    ) -> Result<ExecResult, io::Error>,
+>;
+
 impl<Output, Error> Command<Output, Error>
 where
     Output: 'static,
@@ -160,26 +232,40 @@ where
     /// The output mapping will imply if stdout/stderr is captured and how the
     /// captured output is mapped to a `Result<Self::Output, Self::Error>`.
     ///
+    /// If there is a [`program_override()`] for given program (e.g. set through
+    /// [`set_program_override()`] or a `MAPPED_COMMAND_PROGRAM_<NAME>` env variable)
+    /// the replacement is used as program instead.
     pub fn new(
         program: impl Into<OsString>,
         return_settings: impl OutputMapping<Output = Output, Error = Error>,
     ) -> Self {
+        let program = program.into();
         Command {
-            program: program.into(),
+            program: program_override(&program).unwrap_or(program),
             arguments: Vec::new(),
             env_updates: HashMap::new(),
             check_exit_status: true,
             inherit_env: true,
+            env_inherit_filter: None,
+            env_diff_in_errors: false,
+            validate_before_spawn: false,
+            secret_arguments: BTreeSet::new(),
+            secret_env_vars: HashSet::new(),
             expected_exit_status: ExitStatus::Code(0),
             return_settings: Some(Box::new(return_settings) as _),
             working_directory_override: None,
+            #[cfg(unix)]
+            unix_settings: Default::default(),
             run_callback: Some(Box::new(sys::actual_exec_exec_replacement_callback)),
+            run_callback_replaced: false,
         }
     }
 
     /// Return the program the command will run.
+    ///
+    /// This is the effective program, i.e. with the [`program_override()`] applied.
     pub fn program(&self) -> &OsStr {
-        &*self.program
+        &self.program
     }
 
     /// Returns the arguments passed the the program when run.
@@ -266,9 +352,15 @@ where
     ///   add new ones if no variable with given key was inherited
     /// - [`EnvChange::Remove`] can be used to remove an inherited (or previously added)
     ///   env variable
+    /// - [`EnvChange::Prepend`] and [`EnvChange::Append`] can be used to extend inherited
+    ///   path lists like `PATH`
     /// - [`EnvChange::Inherit`] can be used to state a env variable should be inherited
-    ///   even if `inherit_env` is `false`. If `inherit_env` is true this will have no
-    ///   effect.
+    ///   even if `inherit_env` is `false` (or it's not matched by the
+    ///   [`Command::env_inherit_filter()`]). If `inherit_env` is true this will
+    ///   otherwise have no effect.
+    ///
+    /// Which variables are inherited can be restricted by name and pattern using
+    /// [`Command::with_env_inherit_filter()`].
     pub fn inherit_env(&self) -> bool {
         self.inherit_env
     }
@@ -281,6 +373,90 @@ where
         self
     }
 
+    /// Returns the patterns restricting which env variables are inherited, if any.
+    ///
+    /// If `Some` and [`Command::inherit_env()`] is true only env variables whose name
+    /// matches at least one of the patterns are inherited. Env updates are still applied
+    /// as normal, e.g. [`EnvChange::Inherit`] will inherit a variable even if it's not
+    /// matched by any pattern.
+    ///
+    /// By default this is `None`, i.e. all env variables are inherited.
+    pub fn env_inherit_filter(&self) -> Option<&[EnvPattern]> {
+        self.env_inherit_filter.as_deref()
+    }
+
+    /// Returns this command with a filter restricting which env variables are inherited.
+    ///
+    /// The patterns can be exact names, prefixes, globs or regexes, see [`EnvPattern`]:
+    ///
+    /// ```
+    /// # use mapped_command::{Command, ReturnNothing};
+    /// let cmd = Command::new("cargo", ReturnNothing)
+    ///     .with_env_inherit_filter(["PATH", "HOME", "LC_*", "CARGO_*", "*_PROXY"]);
+    /// ```
+    ///
+    /// See [`Command::env_inherit_filter()`].
+    pub fn with_env_inherit_filter<P>(mut self, patterns: impl IntoIterator<Item = P>) -> Self
+    where
+        P: Into<EnvPattern>,
+    {
+        self.env_inherit_filter = Some(patterns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Returns this command with the `C` locale forced.
+    ///
+    /// This sets `LC_ALL=C` and `LANG=C` and removes `LANGUAGE`, so that the output of
+    /// the sub-process doesn't depend on the locale of the user, which is useful if the
+    /// output is parsed.
+    ///
+    /// This is done using env updates, so it's visible through [`Command::env_updates()`]
+    /// and [`Command::create_expected_env_iter()`] and can be overridden by later env updates.
+    pub fn with_c_locale(self) -> Self {
+        self.with_env_updates(vec![
+            ("LC_ALL", EnvChange::Set("C".into())),
+            ("LANG", EnvChange::Set("C".into())),
+            ("LANGUAGE", EnvChange::Remove),
+        ])
+    }
+
+    /// Returns this command with a minimal env only inheriting [`HERMETIC_ENV_VARS`].
+    ///
+    /// This enables [`Command::inherit_env()`] and replaces the
+    /// [`Command::env_inherit_filter()`] with one only matching the [`HERMETIC_ENV_VARS`].
+    /// Env updates are applied as normal, so previous or later updates (e.g. a custom
+    /// `PATH`) are kept and [`EnvChange::Prepend`]/[`EnvChange::Append`] extend the
+    /// inherited value.
+    ///
+    /// ```
+    /// # use mapped_command::{Command, ReturnNothing};
+    /// let cmd = Command::new("make", ReturnNothing)
+    ///     .with_hermetic_env()
+    ///     .with_c_locale();
+    /// ```
+    pub fn with_hermetic_env(self) -> Self {
+        self.with_inherit_env(true).with_env_inherit_filter(
+            HERMETIC_ENV_VARS
+                .iter()
+                .map(|&var| EnvPattern::Exact(var.into())),
+        )
+    }
+
+    /// Returns the value given env variable has if it's inherited (ignoring env updates).
+    fn inherited_env_var(&self, name: &OsStr) -> Option<OsString> {
+        if self.inherit_env() && self.passes_env_inherit_filter(name) {
+            env::var_os(name)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if given env variable passes the [`Command::env_inherit_filter()`].
+    fn passes_env_inherit_filter(&self, name: &OsStr) -> bool {
+        self.env_inherit_filter()
+            .is_none_or(|patterns| patterns.iter().any(|pattern| pattern.matches(name)))
+    }
+
     /// Returns a map with all env variables the sub-process spawned by this command would have
     /// if the current processes env is not changed.
     ///
@@ -298,7 +474,9 @@ where
     /// Note that this function + `std::env::set_var()` is not unsafe it might just have a
     /// very unexpected result. Except if `env::set_var()` + reading env races are inherently
     /// unsafe on your system, in which case this has nothing to do with this function.
-    pub fn create_expected_env_iter(&self) -> impl Iterator<Item = (Cow<OsStr>, Cow<OsStr>)> {
+    pub fn create_expected_env_iter(
+        &self,
+    ) -> impl Iterator<Item = (Cow<'_, OsStr>, Cow<'_, OsStr>)> {
         let inherit = if self.inherit_env() {
             Some(env::vars_os())
         } else {
@@ -334,21 +512,22 @@ where
                     fused_opt_iter_next!(&mut self.inherit, |(key, val)| {
                         match self.self_.env_updates.get(&key) {
                             Some(_) => continue,
+                            None if !self.self_.passes_env_inherit_filter(&key) => continue,
                             None => return Some((Cow::Owned(key), Cow::Owned(val))),
                         }
                     });
                     fused_opt_iter_next!(&mut self.update, |(key, change)| {
                         match change {
                             EnvChange::Set(val) => {
-                                return Some((Cow::Borrowed(&key), Cow::Borrowed(&val)));
+                                return Some((Cow::Borrowed(key), Cow::Borrowed(val)));
                             }
                             EnvChange::Inherit => {
                                 // Mostly used if inherit_var is valse in which case we *should* not
                                 // have done aboves loop-part on vars_os. We could "optimize" this to
                                 // handle Inherit in aboves loop if we run that loop, but why add that
                                 // complexity?
-                                if let Some(val) = env::var_os(&key) {
-                                    return Some((Cow::Borrowed(&key), Cow::Owned(val)));
+                                if let Some(val) = env::var_os(key) {
+                                    return Some((Cow::Borrowed(key), Cow::Owned(val)));
                                 } else {
                                     continue;
                                 }
@@ -356,6 +535,16 @@ where
                             EnvChange::Remove => {
                                 continue;
                             }
+                            EnvChange::Prepend(paths) => {
+                                let inherited = self.self_.inherited_env_var(key);
+                                let val = join_path_lists(Some(paths), inherited.as_deref());
+                                return Some((Cow::Borrowed(key), Cow::Owned(val)));
+                            }
+                            EnvChange::Append(paths) => {
+                                let inherited = self.self_.inherited_env_var(key);
+                                let val = join_path_lists(inherited.as_deref(), Some(paths));
+                                return Some((Cow::Borrowed(key), Cow::Owned(val)));
+                            }
                         }
                     });
                     return None;
@@ -369,7 +558,7 @@ where
     /// If `None` is returned it means no override is set and the working directory will be inherited
     /// from the spawning process.
     pub fn working_directory_override(&self) -> Option<&Path> {
-        self.working_directory_override.as_ref().map(|s| &**s)
+        self.working_directory_override.as_deref()
     }
 
     /// Replaces the working directory override.
@@ -444,17 +633,39 @@ where
     ///    fail.
     /// 4. if 3 doesn't fail now map captured outputs to a `Result<Output, Error>`
     ///
+    /// If waiting for the sub-process fails after it was spawned (e.g. because reading
+    /// the captured output failed) the sub-process is killed, it won't outlive the call.
+    ///
     /// If [`Command::with_exec_replacement_callback()`] is used instead of running the
     /// program and capturing the output the given callback is called. The callback
     /// could mock the program execution. The exit status checking and output mapping
     /// are still done as normal.
     ///
+    /// # Performance
+    ///
+    /// If the env is inherited (and no [`Command::env_inherit_filter()`] is set) only the
+    /// env updates are applied on top of it, instead of rebuilding it from
+    /// [`Command::create_expected_env_iter()`].
+    ///
+    /// On targets where `std` spawns processes with `posix_spawn` (e.g. linux with a
+    /// recent glibc, macOS) it's used for this crate's commands too, except if a setting
+    /// needs to run code between fork and exec or `PATH` is changed for a program looked
+    /// up in it. On unix the former is the case for the new session, credentials, resource
+    /// limits, parent death signal, umask, niceness, CPU affinity, inherited fds,
+    /// pseudo-terminal and pre-exec hook settings. Run `cargo bench --bench spawn` to
+    /// compare the spawn overhead with `std`.
+    ///
     /// # Panics
     ///
     /// **This will panic if called in a `exec_replacement_callback`.**
     pub fn run(mut self) -> Result<Output, Error> {
         let expected_exit_status = self.expected_exit_status;
         let check_exit_status = self.check_exit_status;
+        let env_diff = if check_exit_status && self.env_diff_in_errors {
+            Some(Box::new(self.env_diff()))
+        } else {
+            None
+        };
         let return_settings = self
             .return_settings
             .take()
@@ -467,11 +678,13 @@ where
         let result = run_callback(self, &*return_settings)?;
 
         if check_exit_status && result.exit_status != expected_exit_status {
-            return Err(UnexpectedExitStatus {
+            Err(UnexpectedExitStatus {
                 got: result.exit_status,
                 expected: expected_exit_status,
+                forwarded_signals: result.forwarded_signals,
+                env_diff,
             }
-            .into());
+            .into())
         } else {
             let stdout = if return_settings.capture_stdout() {
                 result.stdout
@@ -539,8 +752,14 @@ where
             + 'static,
     ) -> Self {
         self.run_callback = Some(Box::new(callback));
+        self.run_callback_replaced = true;
         self
     }
+
+    /// Returns true if [`Command::with_exec_replacement_callback()`] was used.
+    pub(crate) fn has_exec_replacement_callback(&self) -> bool {
+        self.run_callback_replaced
+    }
 }
 
 /// Trait used to configure what [`Command::run()`] returns.
@@ -583,11 +802,70 @@ pub trait OutputMapping: 'static {
 ///
 /// By default this means the exit status was not 0, but
 /// this can be reconfigured.
+///
+/// If signals were forwarded to the sub-process they are listed in the error message.
+///
+/// If [`Command::with_env_diff_in_errors()`] is enabled the error also contains
+/// the [`Command::env_diff()`], which is then appended to the error message.
 #[derive(Debug, Error)]
-#[error("Unexpected exit status. Got: {got}, Expected: {expected}")]
+#[error(
+    "Unexpected exit status. Got: {got}, Expected: {expected}{}{}",
+    DisplayForwardedSignals(forwarded_signals),
+    DisplayEnvDiff(env_diff.as_deref())
+)]
 pub struct UnexpectedExitStatus {
     got: ExitStatus,
     expected: ExitStatus,
+    forwarded_signals: Vec<i32>,
+    env_diff: Option<Box<EnvDiff>>,
+}
+
+impl UnexpectedExitStatus {
+    /// The exit status the sub-process exited with.
+    pub fn got(&self) -> ExitStatus {
+        self.got
+    }
+
+    /// The exit status which was expected.
+    pub fn expected(&self) -> ExitStatus {
+        self.expected
+    }
+
+    /// The signals which were forwarded to the sub-process, see [`ExecResult::forwarded_signals`].
+    ///
+    /// E.g. this allows detecting that a sub-process exited with an error code because it
+    /// handled a forwarded `SIGINT`.
+    pub fn forwarded_signals(&self) -> &[i32] {
+        &self.forwarded_signals
+    }
+
+    /// The env diff of the failed command, if [`Command::with_env_diff_in_errors()`] is enabled.
+    pub fn env_diff(&self) -> Option<&EnvDiff> {
+        self.env_diff.as_deref()
+    }
+}
+
+struct DisplayForwardedSignals<'a>(&'a [i32]);
+
+impl Display for DisplayForwardedSignals<'_> {
+    fn fmt(&self, fter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            write!(fter, ", Forwarded signals: {:?}", self.0)
+        }
+    }
+}
+
+struct DisplayEnvDiff<'a>(Option<&'a EnvDiff>);
+
+impl Display for DisplayEnvDiff<'_> {
+    fn fmt(&self, fter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(env_diff) => write!(fter, "\nEnv diff:\n{}", env_diff),
+            None => Ok(()),
+        }
+    }
 }
 
 /// A ExitStatus type similar to `std::process::ExitStatus` but which can be created (e.g. for testing).
@@ -655,7 +933,13 @@ pub struct UnexpectedExitStatus {
 ///   But we do "fix" the exit code so that an exit code of `u32::MAX` is still
 ///   `u32::MAX` and not `-1`!.
 ///
+/// # FromStr
+///
+/// The format produced by the `Display` implementation can be parsed back
+/// using [`str::parse()`], e.g. `"0x1"` or (on unix) `"signal(9)"`.
+///
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ExitStatus {
     /// The process exited with an exit code.
     ///
@@ -698,12 +982,9 @@ impl ExitStatus {
     /// only enabled on the unix and window target family. (Note that windows
     /// and unix are currently the only target families as e.g. linux, all BSD's,
     /// OsX, iOs are unix-like enough to count as part of the unix family).
-    #[cfg(any(window, unix))]
+    #[cfg(any(windows, unix))]
     pub fn successful(&self) -> bool {
-        match self {
-            Self::Code(code) if *code == 0 => true,
-            _ => false,
-        }
+        matches!(self, Self::Code(0))
     }
 }
 
@@ -716,6 +997,26 @@ impl Display for ExitStatus {
     }
 }
 
+impl FromStr for ExitStatus {
+    type Err = ParseExitStatusError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if let Some(hex_code) = input.strip_prefix("0x") {
+            // `from_str_radix` accepts a leading `+`, which `Display` never produces.
+            if !hex_code.starts_with(|char: char| char.is_ascii_hexdigit()) {
+                return Err(ParseExitStatusError::new(input));
+            }
+            // Display formats the bits of the `i64`, so negative codes
+            // need to be parsed as `u64` and then be reinterpreted.
+            u64::from_str_radix(hex_code, 16)
+                .map(|code| Self::Code(code as i64))
+                .map_err(|_| ParseExitStatusError::new(input))
+        } else {
+            input.parse().map(Self::OsSpecific)
+        }
+    }
+}
+
 impl Default for ExitStatus {
     fn default() -> Self {
         Self::Code(0)
@@ -758,6 +1059,7 @@ impl_from_and_partial_eq_for_fitting_int!(u8, i8, u16, i16, u32, i32, i64);
 /// all other methods only exist on _some_ targets but not all.** As such
 /// using them can lead to code which only compiles on some targets.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct OpaqueOsExitStatus {
     #[cfg(not(unix))]
     _priv: (),
@@ -829,8 +1131,56 @@ impl Display for OpaqueOsExitStatus {
     }
 }
 
+impl FromStr for OpaqueOsExitStatus {
+    type Err = ParseExitStatusError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        #[cfg(not(unix))]
+        {
+            if input == "NO_EXIT_CODE" {
+                Ok(Self::target_specific_default())
+            } else {
+                Err(ParseExitStatusError::new(input))
+            }
+        }
+        #[cfg(unix)]
+        {
+            input
+                .strip_prefix("signal(")
+                .and_then(|rest| rest.strip_suffix(')'))
+                .and_then(|signal| signal.parse().ok())
+                .map(Self::from_signal_number)
+                .ok_or_else(|| ParseExitStatusError::new(input))
+        }
+    }
+}
+
+/// Parsing an [`ExitStatus`] or [`OpaqueOsExitStatus`] from a string failed.
+///
+/// Only the format produced by the `Display` implementations of
+/// this types can be parsed.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Invalid exit status: {input:?}")]
+pub struct ParseExitStatusError {
+    input: String,
+}
+
+impl ParseExitStatusError {
+    fn new(input: &str) -> Self {
+        ParseExitStatusError {
+            input: input.to_owned(),
+        }
+    }
+
+    /// Returns the string which failed to parse.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+}
+
 /// Used to determine how a env variable should be updated.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum EnvChange {
     /// Remove the env value if it normally would have been set
     ///
@@ -847,6 +1197,50 @@ pub enum EnvChange {
     ///
     /// If environment inheritance is enabled this won't have any effect.
     Inherit,
+
+    /// Prepend given paths to the inherited path list, e.g. to add a directory to `PATH`.
+    ///
+    /// The value can be a single path or a path list using the platform specific
+    /// separator (see `env::split_paths`). The result is joined using `env::join_paths`,
+    /// if a path is in it multiple times only the first occurrence is kept. Empty
+    /// entries (which would mean the current directory) are removed.
+    ///
+    /// If the variable isn't inherited (e.g. because `inherit_env` is false) this
+    /// behaves like [`EnvChange::Set`] (except for the de-duplication).
+    Prepend(OsString),
+
+    /// Append given paths to the inherited path list, e.g. to add a directory to `LD_LIBRARY_PATH`.
+    ///
+    /// Works like [`EnvChange::Prepend`], except that the paths are added at the end.
+    /// This means paths which already are in the inherited path list are not moved.
+    Append(OsString),
+}
+
+/// Joins the two path lists, removing duplicates and empty entries.
+///
+/// Empty entries are removed as they'd mean the current directory, e.g. an empty
+/// inherited `LD_LIBRARY_PATH` must not lead to `/foo:`.
+///
+/// If a path can't be joined (e.g. because it contains a `"` on windows) the paths
+/// are concatenated with the platform separator as is.
+fn join_path_lists(first: Option<&OsStr>, second: Option<&OsStr>) -> OsString {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for path in first.into_iter().chain(second).flat_map(env::split_paths) {
+        if !path.as_os_str().is_empty() && !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    env::join_paths(&paths).unwrap_or_else(|_| {
+        let separator = if cfg!(windows) { ";" } else { ":" };
+        let mut joined = OsString::new();
+        for (idx, path) in paths.iter().enumerate() {
+            if idx > 0 {
+                joined.push(separator);
+            }
+            joined.push(path);
+        }
+        joined
+    })
 }
 
 impl From<&Self> for EnvChange {
@@ -886,6 +1280,7 @@ impl From<&str> for EnvChange {
 
 /// Type used for `exec_replacement_callback` to return mocked output and exit status.
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ExecResult {
     /// The exit status the process did exit with.
     pub exit_status: ExitStatus,
@@ -901,6 +1296,12 @@ pub struct ExecResult {
     /// This must be `Some` if `stderr` is expected to be captured, it must
     /// be `None` if it's expected to not be captured.
     pub stderr: Option<Vec<u8>>,
+
+    /// The signals which were forwarded to the sub-process while it ran.
+    ///
+    /// See `Command::forwarded_signals()`, on non-unix targets this is always empty.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub forwarded_signals: Vec<i32>,
 }
 
 #[cfg(test)]
@@ -932,7 +1333,7 @@ mod tests {
             match self {
                 Self::Io(err) => panic!("unexpected io error: {:?}", err),
                 Self::UnexpectedExitStatus(err) => panic!("unexpected exit status: {:?}", err),
-                Self::Prop(prop_err) => return prop_err,
+                Self::Prop(prop_err) => prop_err,
             }
         }
     }
@@ -995,8 +1396,10 @@ mod tests {
                 #[test]
                 fn the_used_program_can_be_queried(s in any::<OsString>()) {
                     let s = OsStr::new(&*s);
+                    // A `MAPPED_COMMAND_PROGRAM_*` variable in the env of the test would replace it.
+                    prop_assume!(program_override(s).is_none());
                     let cmd = Command::new(s, ReturnNothing);
-                    prop_assert_eq!(&*cmd.program(), s)
+                    prop_assert_eq!(cmd.program(), s)
                 }
             }
         }
@@ -1054,9 +1457,7 @@ mod tests {
             #[test]
             fn run_can_lead_to_and_io_error() {
                 let res = Command::new("foo", ReturnNothing)
-                    .with_exec_replacement_callback(|_, _| {
-                        Err(io::Error::new(io::ErrorKind::Other, "random"))
-                    })
+                    .with_exec_replacement_callback(|_, _| Err(io::Error::other("random")))
                     .run();
 
                 res.unwrap_err();
@@ -1197,7 +1598,8 @@ mod tests {
                             Ok(ExecResult {
                                 exit_status: 0.into(),
                                 stdout: if capture_stdout { Some(Vec::new()) } else { None },
-                                stderr: if capture_stderr { Some(Vec::new()) } else { None }
+                                stderr: if capture_stderr { Some(Vec::new()) } else { None },
+                                ..Default::default()
                             })
                         })
                         .run()
@@ -1228,7 +1630,8 @@ mod tests {
                             Ok(ExecResult {
                                 exit_status: 0.into(),
                                 stdout: if capture_stdout { Some(Vec::new()) } else { None },
-                                stderr: if capture_stderr { Some(Vec::new()) } else { None }
+                                stderr: if capture_stderr { Some(Vec::new()) } else { None },
+                                ..Default::default()
                             })
                         })
                         .run()
@@ -1249,7 +1652,6 @@ mod tests {
             #[test]
             fn create_expected_env_iter_includes_the_current_env_by_default() {
                 let process_env = env::vars_os()
-                    .into_iter()
                     .map(|(k, v)| (Cow::Owned(k), Cow::Owned(v)))
                     .collect::<HashMap<_, _>>();
                 let cmd = Command::new("foo", ReturnNothing);
@@ -1260,18 +1662,133 @@ mod tests {
             #[test]
             fn by_default_env_is_inherited() {
                 let cmd = Command::new("foo", ReturnNothing);
-                assert_eq!(cmd.inherit_env(), true);
+                assert!(cmd.inherit_env());
                 //FIXME fluky if there is no single ENV variable set
                 assert_ne!(cmd.create_expected_env_iter().count(), 0);
             }
 
+            #[test]
+            fn by_default_no_env_inherit_filter_is_set() {
+                let cmd = Command::new("foo", ReturnNothing);
+                assert!(cmd.env_inherit_filter().is_none());
+            }
+
             #[test]
             fn inheritance_of_env_variables_can_be_disabled() {
                 let cmd = Command::new("foo", ReturnNothing).with_inherit_env(false);
-                assert_eq!(cmd.inherit_env(), false);
+                assert!(!cmd.inherit_env());
                 assert_eq!(cmd.create_expected_env_iter().count(), 0);
             }
 
+            #[cfg(unix)]
+            #[test]
+            fn path_lists_are_joined_without_duplicates_and_empty_entries() {
+                let join = |first: &str, second: Option<&str>| {
+                    join_path_lists(Some(OsStr::new(first)), second.map(OsStr::new))
+                };
+                assert_eq!(join("/foo", Some("")), "/foo");
+                assert_eq!(join("", Some("/foo")), "/foo");
+                assert_eq!(join("/foo", Some("/bin::/usr/bin:")), "/foo:/bin:/usr/bin");
+                assert_eq!(join("::/bin", Some("/foo:")), "/bin:/foo");
+                assert_eq!(join("/bin:/foo", Some("/foo")), "/bin:/foo");
+                assert_eq!(join("/foo", Some("/bin:/foo")), "/foo:/bin");
+                assert_eq!(join("/foo:/foo", None), "/foo");
+                assert_eq!(join("::", Some("")), "");
+            }
+
+            #[test]
+            fn path_changes_drop_empty_entries() {
+                let separator = if cfg!(windows) { ";" } else { ":" };
+                let value = format!("{0}/foo{0}{0}/bar{0}", separator);
+                let cmd = Command::new("foo", ReturnNothing)
+                    .with_inherit_env(false)
+                    .with_env_update("PATH", EnvChange::Prepend(value.into()));
+
+                let produced_env = cmd.create_expected_env_iter().collect::<HashMap<_, _>>();
+                assert_eq!(
+                    produced_env[OsStr::new("PATH")],
+                    OsString::from(format!("/foo{}/bar", separator))
+                );
+            }
+
+            #[test]
+            fn the_c_locale_can_be_forced() {
+                let cmd = Command::new("foo", ReturnNothing)
+                    .with_env_update("LANGUAGE", "de")
+                    .with_c_locale();
+                let produced_env = cmd.create_expected_env_iter().collect::<HashMap<_, _>>();
+
+                assert_eq!(produced_env[OsStr::new("LC_ALL")], OsStr::new("C"));
+                assert_eq!(produced_env[OsStr::new("LANG")], OsStr::new("C"));
+                assert_eq!(produced_env.get(OsStr::new("LANGUAGE")), None);
+                assert_eq!(
+                    cmd.env_updates().get(OsStr::new("LANGUAGE")),
+                    Some(&EnvChange::Remove)
+                );
+            }
+
+            #[test]
+            fn hermetic_env_only_inherits_a_minimal_set_of_variables() {
+                let cmd = Command::new("foo", ReturnNothing)
+                    .with_env_update("PATH", "/custom/bin")
+                    .with_hermetic_env()
+                    .with_env_update("FOO", "bar");
+                assert!(cmd.inherit_env());
+                assert_eq!(
+                    cmd.env_inherit_filter().map(<[_]>::len),
+                    Some(HERMETIC_ENV_VARS.len())
+                );
+                assert_eq!(
+                    cmd.env_updates().get(OsStr::new("PATH")),
+                    Some(&EnvChange::Set("/custom/bin".into()))
+                );
+
+                let produced_env = cmd
+                    .create_expected_env_iter()
+                    .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                    .collect::<HashMap<_, _>>();
+
+                let mut expected_env = HERMETIC_ENV_VARS
+                    .iter()
+                    .filter_map(|&var| Some((OsString::from(var), env::var_os(var)?)))
+                    .collect::<HashMap<_, _>>();
+                expected_env.insert("PATH".into(), "/custom/bin".into());
+                expected_env.insert("FOO".into(), "bar".into());
+                assert_eq!(produced_env, expected_env);
+            }
+
+            #[test]
+            fn hermetic_env_keeps_the_inherited_value_for_path_changes() {
+                let inherited = match env::var_os("PATH") {
+                    Some(inherited) => inherited,
+                    None => return,
+                };
+                let prepend = EnvChange::Prepend("/opt/bin".into());
+                let commands = vec![
+                    Command::new("foo", ReturnNothing)
+                        .with_hermetic_env()
+                        .with_env_update("PATH", prepend.clone()),
+                    Command::new("foo", ReturnNothing)
+                        .with_env_update("PATH", prepend)
+                        .with_hermetic_env(),
+                ];
+
+                for cmd in commands {
+                    let produced_env = cmd.create_expected_env_iter().collect::<HashMap<_, _>>();
+                    let paths =
+                        env::split_paths(&produced_env[OsStr::new("PATH")]).collect::<Vec<_>>();
+                    assert_eq!(paths[0], Path::new("/opt/bin"));
+                    for path in env::split_paths(&inherited) {
+                        assert!(
+                            path.as_os_str().is_empty() || paths.contains(&path),
+                            "{:?} missing in {:?}",
+                            path,
+                            paths
+                        );
+                    }
+                }
+            }
+
             proptest! {
                 #[test]
                 fn new_env_variables_can_be_added(
@@ -1280,12 +1797,12 @@ mod tests {
                     value in any::<OsString>(),
                     map1 in proptest::collection::hash_map(
                         any::<OsString>(),
-                        any::<OsString>().prop_map(|s| EnvChange::Set(s)),
+                        any::<OsString>().prop_map(EnvChange::Set),
                         0..4
                     ),
                     map2 in proptest::collection::hash_map(
                         any::<OsString>(),
-                        any::<OsString>().prop_map(|s| EnvChange::Set(s)),
+                        any::<OsString>().prop_map(EnvChange::Set),
                         0..4
                     ),
                 ) {
@@ -1354,11 +1871,11 @@ mod tests {
                         .with_env_update(&inherit, EnvChange::Inherit);
 
                     assert_eq!(cmd.create_expected_env_iter().count(), 1);
-                    let got_value = cmd.create_expected_env_iter().find(|(k,_v)| &*k==&*inherit)
+                    let got_value = cmd.create_expected_env_iter().find(|(k,_v)| k==&*inherit)
                         .map(|(_k,v)| v);
                     assert_eq!(
-                        expected_val.as_ref().map(|v|&**v),
-                        got_value.as_ref().map(|v|&**v)
+                        expected_val.as_deref(),
+                        got_value.as_deref()
                     );
                 }
 
@@ -1373,21 +1890,103 @@ mod tests {
                         .with_inherit_env(false);
 
                     assert_eq!(cmd.create_expected_env_iter().count(), 1);
-                    let got_value = cmd.create_expected_env_iter().find(|(k,_v)| &*k==&*inherit)
+                    let got_value = cmd.create_expected_env_iter().find(|(k,_v)| k==&*inherit)
                         .map(|(_k,v)| v);
                     assert_eq!(
-                        expected_val.as_ref().map(|v|&**v),
-                        got_value.as_ref().map(|v|&**v)
+                        expected_val.as_deref(),
+                        got_value.as_deref()
                     );
                 }
 
+                //FIXME on CI this test can leak secrets if it fails
+                #[test]
+                fn the_env_inherit_filter_restricts_inherited_variables(
+                    cmd in any::<OsString>(),
+                    allowed in proptest::sample::select(env::vars_os().map(|(k,_v)| k).collect::<Vec<_>>()),
+                    inherit in proptest::sample::select(env::vars_os().map(|(k,_v)| k).collect::<Vec<_>>()),
+                ) {
+                    let cmd = Command::new(cmd, ReturnNothing)
+                        .with_env_inherit_filter(vec![EnvPattern::Exact(allowed.clone())])
+                        .with_env_update(&inherit, EnvChange::Inherit);
+
+                    let values = cmd.create_expected_env_iter()
+                        .map(|(k,v)| (k.into_owned(), v.into_owned()))
+                        .collect::<HashMap<_,_>>();
+
+                    let mut expected = HashMap::new();
+                    for key in [&allowed, &inherit] {
+                        expected.insert(key.clone(), env::var_os(key).unwrap());
+                    }
+                    prop_assert_eq!(values, expected);
+                }
+
+                //FIXME on CI this test can leak secrets if it fails
+                #[test]
+                fn the_env_inherit_filter_supports_prefixes(
+                    cmd in any::<OsString>(),
+                    key in proptest::sample::select(env::vars_os().map(|(k,_v)| k).collect::<Vec<_>>()),
+                    prefix_len in 0usize..4,
+                ) {
+                    let key_str = key.to_string_lossy();
+                    let prefix = key_str.chars().take(prefix_len).collect::<String>();
+                    prop_assume!(key.to_str().is_some() && !prefix.contains(['*', '?', '[']));
+
+                    let cmd = Command::new(cmd, ReturnNothing)
+                        .with_env_inherit_filter([format!("{}*", prefix)]);
+
+                    let keys = cmd.create_expected_env_iter()
+                        .map(|(k,_v)| k.into_owned())
+                        .collect::<Vec<_>>();
+
+                    prop_assert!(keys.contains(&key));
+                    prop_assert!(keys.iter().all(|k| k.to_string_lossy().starts_with(&prefix)));
+                }
+
+                #[test]
+                fn the_env_inherit_filter_does_not_affect_set_variables(
+                    cmd in any::<OsString>(),
+                    value in any::<OsString>(),
+                ) {
+                    const KEY: &str = "____MAPPED_COMMAND__FILTERED_OUT____";
+                    let cmd = Command::new(cmd, ReturnNothing)
+                        .with_env_inherit_filter(Vec::<EnvPattern>::new())
+                        .with_env_update(KEY, EnvChange::Set(value.clone()));
+
+                    let values = cmd.create_expected_env_iter()
+                        .map(|(k,v)| (k.into_owned(), v.into_owned()))
+                        .collect::<Vec<_>>();
+                    prop_assert_eq!(values, vec![(OsString::from(KEY), value)]);
+                }
+
+                #[test]
+                fn path_changes_of_not_inherited_variables_only_contain_the_new_paths(
+                    cmd in any::<OsString>(),
+                    paths in proptest::collection::vec("/[a-z]{1,3}", 1..5),
+                ) {
+                    let value = env::join_paths(&paths).unwrap();
+                    let cmd = Command::new(cmd, ReturnNothing)
+                        .with_inherit_env(false)
+                        .with_env_update("PATH", EnvChange::Append(value));
+
+                    let mut expected = Vec::new();
+                    for path in &paths {
+                        if !expected.contains(path) {
+                            expected.push(path.clone());
+                        }
+                    }
+                    let values = cmd.create_expected_env_iter()
+                        .map(|(k,v)| (k.into_owned(), v.into_owned()))
+                        .collect::<Vec<_>>();
+                    prop_assert_eq!(values, vec![("PATH".into(), env::join_paths(expected).unwrap())]);
+                }
+
                 //FIXME on CI this test can leak secrets if it fails
                 #[test]
                 fn setting_inherit_does_not_affect_anything_if_we_anyway_inherit_all(
                     cmd in any::<OsString>(),
                     pointless_inherit in proptest::sample::select(env::vars_os().map(|(k,_v)| k).collect::<Vec<_>>()),
                 ) {
-                    const NON_EXISTING_VAR_KEY: &'static str = "____MAPPED_COMMAND__THIS_SHOULD_NOT_EXIST_AS_ENV_VARIABLE____";
+                    const NON_EXISTING_VAR_KEY: &str = "____MAPPED_COMMAND__THIS_SHOULD_NOT_EXIST_AS_ENV_VARIABLE____";
                     assert_eq!(env::var_os(NON_EXISTING_VAR_KEY), None);
 
                     let expected_values = env::vars_os()
@@ -1432,10 +2031,10 @@ mod tests {
                     let cmd = Command::new(cmd, ReturnNothing)
                         .with_working_directory_override(wd_override.as_ref());
 
-                    assert_eq!(cmd.working_directory_override(), wd_override.as_ref().map(|i|&**i));
+                    assert_eq!(cmd.working_directory_override(), wd_override.as_deref());
 
                     let cmd = cmd.with_working_directory_override(wd_override2.as_ref());
-                    assert_eq!(cmd.working_directory_override(), wd_override2.as_ref().map(|i|&**i));
+                    assert_eq!(cmd.working_directory_override(), wd_override2.as_deref());
                 }
             }
         }
@@ -1453,7 +2052,7 @@ mod tests {
             #[test]
             fn by_default_exit_status_checking_is_enabled() {
                 let cmd = Command::new("foo", ReturnNothing);
-                assert_eq!(cmd.check_exit_status(), true);
+                assert!(cmd.check_exit_status());
             }
 
             #[test]
@@ -1488,7 +2087,7 @@ mod tests {
                     .with_check_exit_status(false)
                     .with_expected_exit_status(0);
 
-                assert_eq!(cmd.check_exit_status(), true);
+                assert!(cmd.check_exit_status());
             }
 
             proptest! {
@@ -1526,7 +2125,7 @@ mod tests {
                         .run();
 
                     match res {
-                        Err(CommandExecutionError::UnexpectedExitStatus(UnexpectedExitStatus {got, expected})) => {
+                        Err(CommandExecutionError::UnexpectedExitStatus(UnexpectedExitStatus {got, expected, ..})) => {
                             assert_eq!(expected, exit_status);
                             assert_eq!(got, exit_status+offset);
                         },
@@ -1563,16 +2162,17 @@ mod tests {
                 let cmd = Command::new("some_cmd", ReturnStdoutAndErr)
                     .with_exec_replacement_callback(move |for_cmd, _| {
                         *(*was_run_).borrow_mut() = true;
-                        assert_eq!(&*for_cmd.program(), "some_cmd");
+                        assert_eq!(for_cmd.program(), "some_cmd");
                         Ok(ExecResult {
                             exit_status: 0.into(),
                             stdout: Some("result=12".to_owned().into()),
                             stderr: Some(Vec::new()),
+                            ..Default::default()
                         })
                     });
 
                 let res = cmd.run().unwrap();
-                assert_eq!(*was_run.borrow_mut(), true);
+                assert!(*was_run.borrow_mut());
                 assert_eq!(&*res.stdout, "result=12".as_bytes());
                 assert_eq!(&*res.stderr, "".as_bytes());
             }
@@ -1644,6 +2244,126 @@ mod tests {
                 assert_eq!(status, -13i64);
             }
         }
+
+        mod from_str {
+            use crate::{utils::arbitrary_exit_status, ExitStatus, OpaqueOsExitStatus};
+            use proptest::prelude::*;
+
+            #[test]
+            fn parse_hex_exit_code() {
+                assert_eq!("0x7F".parse::<ExitStatus>(), Ok(ExitStatus::Code(0x7F)));
+                assert_eq!("0x7f".parse::<ExitStatus>(), Ok(ExitStatus::Code(0x7F)));
+                assert_eq!(
+                    "0xFFFFFFFFFFFFFFFF".parse::<ExitStatus>(),
+                    Ok(ExitStatus::Code(-1))
+                );
+            }
+
+            #[test]
+            #[cfg(unix)]
+            fn parse_signal_on_unix() {
+                assert_eq!(
+                    "signal(9)".parse::<ExitStatus>(),
+                    Ok(ExitStatus::OsSpecific(
+                        OpaqueOsExitStatus::from_signal_number(9)
+                    ))
+                );
+            }
+
+            #[test]
+            fn parsing_invalid_input_fails() {
+                for input in &[
+                    "", "0x", "12", "0xZZ", "0x+1", "0x-1", "signal(9", "signal()", "NO_EXIT",
+                ] {
+                    let err = input.parse::<ExitStatus>().unwrap_err();
+                    assert_eq!(err.input(), *input);
+                }
+                "0x1".parse::<OpaqueOsExitStatus>().unwrap_err();
+            }
+
+            proptest! {
+                #[test]
+                fn display_output_can_be_parsed(
+                    exit_status in arbitrary_exit_status()
+                ) {
+                    let parsed = exit_status.to_string().parse::<ExitStatus>();
+                    prop_assert_eq!(parsed, Ok(exit_status));
+                }
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        mod serde {
+            use crate::{utils::arbitrary_exit_status, ExitStatus};
+            use proptest::prelude::*;
+
+            proptest! {
+                #[test]
+                fn serialization_round_trip(
+                    exit_status in arbitrary_exit_status()
+                ) {
+                    let json = serde_json::to_string(&exit_status).unwrap();
+                    let got: ExitStatus = serde_json::from_str(&json).unwrap();
+                    prop_assert_eq!(got, exit_status);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod EnvChange {
+        #![allow(non_snake_case)]
+
+        mod serde {
+            use crate::EnvChange;
+            use proptest::prelude::*;
+            use std::ffi::OsString;
+
+            proptest! {
+                #[test]
+                fn serialization_round_trip(
+                    change in prop_oneof![
+                        Just(EnvChange::Remove),
+                        Just(EnvChange::Inherit),
+                        any::<OsString>().prop_map(EnvChange::Set),
+                        any::<OsString>().prop_map(EnvChange::Prepend),
+                        any::<OsString>().prop_map(EnvChange::Append)
+                    ]
+                ) {
+                    let json = serde_json::to_string(&change).unwrap();
+                    let got: EnvChange = serde_json::from_str(&json).unwrap();
+                    prop_assert_eq!(got, change);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod ExecResult {
+        #![allow(non_snake_case)]
+
+        mod serde {
+            use crate::{utils::arbitrary_exit_status, ExecResult};
+            use proptest::prelude::*;
+
+            proptest! {
+                #[test]
+                fn serialization_round_trip(
+                    exit_status in arbitrary_exit_status(),
+                    stdout in proptest::option::of(any::<Vec<u8>>()),
+                    stderr in proptest::option::of(any::<Vec<u8>>()),
+                    forwarded_signals in any::<Vec<i32>>()
+                ) {
+                    let result = ExecResult { exit_status, stdout, stderr, forwarded_signals };
+                    let json = serde_json::to_string(&result).unwrap();
+                    let got: ExecResult = serde_json::from_str(&json).unwrap();
+                    prop_assert_eq!(got.exit_status, result.exit_status);
+                    prop_assert_eq!(got.stdout, result.stdout);
+                    prop_assert_eq!(got.stderr, result.stderr);
+                    prop_assert_eq!(got.forwarded_signals, result.forwarded_signals);
+                }
+            }
+        }
     }
 
     #[cfg(unix)]
@@ -0,0 +1,142 @@
+//! Minimal wrappers around the linux pidfd syscalls.
+//!
+//! A pidfd refers to a specific process, unlike a PID it can't be recycled
+//! while we hold it. So signals sent through it never reach an unrelated process.
+use std::{
+    io, mem,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    ptr,
+    time::{Duration, Instant},
+};
+
+/// Opens a pidfd for given process.
+///
+/// Returns `None` if pidfds are not supported by the kernel (before linux 5.3).
+pub(crate) fn open(pid: libc::pid_t) -> Result<Option<OwnedFd>, io::Error> {
+    // SAFETY: `pidfd_open` only takes integer arguments.
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd == -1 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOSYS) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    // SAFETY: the syscall returned a new fd which we now own.
+    Ok(Some(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) }))
+}
+
+/// Sends given signal to the process referred to by the pidfd.
+pub(crate) fn send_signal(pidfd: &OwnedFd, signal: i32) -> Result<(), io::Error> {
+    // SAFETY: a null `siginfo_t` is explicitly allowed.
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            signal,
+            ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Waits until the process referred to by the pidfd exited, without reaping it.
+///
+/// Returns `false` if the process didn't exit within given timeout (if any).
+pub(crate) fn wait_exited(pidfd: &OwnedFd, timeout: Option<Duration>) -> Result<bool, io::Error> {
+    if let Some(timeout) = timeout {
+        let deadline = Instant::now() + timeout;
+        // A pidfd becomes readable once the process exited.
+        let mut poll_fd = libc::pollfd {
+            fd: pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // Round up, to not wake up (and fail) slightly before the timeout passed.
+            let millis = remaining.as_nanos().div_ceil(1_000_000);
+            let millis = millis.min(libc::c_int::MAX as u128) as libc::c_int;
+            // SAFETY: `poll_fd` is a valid array of one `pollfd`.
+            match unsafe { libc::poll(&mut poll_fd, 1, millis) } {
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(err);
+                }
+                0 => return Ok(false),
+                _ => break,
+            }
+        }
+    }
+
+    loop {
+        // SAFETY: zeroed is a valid `siginfo_t`, it's only written to.
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        let result = unsafe {
+            libc::waitid(
+                libc::P_PIDFD,
+                pidfd.as_raw_fd() as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if result == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        return Ok(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod wait_exited {
+        use super::super::{open, wait_exited};
+        use std::{os::unix::thread::JoinHandleExt, process, thread, time::Duration};
+
+        extern "C" fn ignore_signal(_: libc::c_int) {}
+
+        #[test]
+        fn interrupting_signals_are_retried() {
+            // SIGURG is ignored by default, so a handler for it doesn't affect other tests.
+            // Without `SA_RESTART` the signal makes `poll` fail with `EINTR`.
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = ignore_signal as extern "C" fn(libc::c_int) as usize;
+                assert_eq!(
+                    libc::sigaction(libc::SIGURG, &action, std::ptr::null_mut()),
+                    0
+                );
+            }
+            let mut child = process::Command::new("sleep").arg("0.5").spawn().unwrap();
+            let pidfd = match open(child.id() as libc::pid_t).unwrap() {
+                Some(pidfd) => pidfd,
+                None => {
+                    child.kill().unwrap();
+                    child.wait().unwrap();
+                    return;
+                }
+            };
+
+            let waiter = thread::spawn(move || wait_exited(&pidfd, Some(Duration::from_secs(10))));
+            thread::sleep(Duration::from_millis(100));
+            unsafe { libc::pthread_kill(waiter.as_pthread_t(), libc::SIGURG) };
+
+            assert!(waiter.join().unwrap().unwrap());
+            child.wait().unwrap();
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! A process wide registry substituting programs, see [`set_program_override()`].
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{OsStr, OsString},
+    path::Path,
+    sync::RwLock,
+};
+
+/// The prefix of env variables overriding programs, see [`program_override()`].
+pub const PROGRAM_OVERRIDE_ENV_PREFIX: &str = "MAPPED_COMMAND_PROGRAM_";
+
+static PROGRAM_OVERRIDES: RwLock<BTreeMap<OsString, OsString>> = RwLock::new(BTreeMap::new());
+
+/// Makes all commands created afterwards for `program` run `replacement` instead.
+///
+/// The override is applied by [`Command::new()`] if the program passed to it is exactly
+/// `program`, so [`Command::program()`] returns the replacement. Replaces any previous
+/// override for `program`.
+///
+/// ```
+/// use mapped_command::{set_program_override, Command, ReturnNothing};
+///
+/// set_program_override("git", "/opt/git/bin/git");
+/// let cmd = Command::new("git", ReturnNothing);
+/// assert_eq!(cmd.program(), "/opt/git/bin/git");
+/// ```
+///
+/// [`Command::new()`]: crate::Command::new
+/// [`Command::program()`]: crate::Command::program
+pub fn set_program_override(program: impl Into<OsString>, replacement: impl Into<OsString>) {
+    PROGRAM_OVERRIDES
+        .write()
+        .unwrap_or_else(|poison| poison.into_inner())
+        .insert(program.into(), replacement.into());
+}
+
+/// Removes the override set with [`set_program_override()`] for `program`, returning it.
+///
+/// This doesn't affect overrides set through env variables.
+pub fn remove_program_override(program: impl AsRef<OsStr>) -> Option<OsString> {
+    PROGRAM_OVERRIDES
+        .write()
+        .unwrap_or_else(|poison| poison.into_inner())
+        .remove(program.as_ref())
+}
+
+/// Returns the program [`Command::new()`] will use instead of given program, if any.
+///
+/// Overrides are looked up:
+///
+/// 1. in the overrides set with [`set_program_override()`]
+/// 2. if the program is a plain name (i.e. contains no path separator) in the env
+///    variable `MAPPED_COMMAND_PROGRAM_<NAME>` of the current process, where `<NAME>` is
+///    the program name in upper case with all characters except ASCII letters and digits
+///    replaced by `_`, e.g. `MAPPED_COMMAND_PROGRAM_GIT_LFS` for `git-lfs`. Empty values
+///    are ignored.
+///
+/// Program names differing only in case or in characters other than ASCII letters and
+/// digits share the same env variable, e.g. `git-lfs`, `git_lfs` and `Git.LFS` are all
+/// overridden by `MAPPED_COMMAND_PROGRAM_GIT_LFS`. Use [`set_program_override()`] to
+/// override only one of them.
+///
+/// [`Command::new()`]: crate::Command::new
+pub fn program_override(program: &OsStr) -> Option<OsString> {
+    lookup_program_override(program, |name| env::var_os(name))
+}
+
+fn lookup_program_override(
+    program: &OsStr,
+    lookup_env: impl FnOnce(&str) -> Option<OsString>,
+) -> Option<OsString> {
+    let overrides = PROGRAM_OVERRIDES
+        .read()
+        .unwrap_or_else(|poison| poison.into_inner());
+    if let Some(replacement) = overrides.get(program) {
+        return Some(replacement.clone());
+    }
+    drop(overrides);
+
+    if Path::new(program).components().count() != 1 || program.is_empty() {
+        return None;
+    }
+    lookup_env(&program_override_env_var(program)).filter(|replacement| !replacement.is_empty())
+}
+
+/// Returns the name of the env variable overriding given program.
+fn program_override_env_var(program: &OsStr) -> String {
+    let mut name = String::from(PROGRAM_OVERRIDE_ENV_PREFIX);
+    name.extend(program.to_string_lossy().chars().map(|char| {
+        if char.is_ascii_alphanumeric() {
+            char.to_ascii_uppercase()
+        } else {
+            '_'
+        }
+    }));
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod program_override {
+        use super::super::{lookup_program_override, program_override_env_var};
+        use crate::{remove_program_override, set_program_override, Command, ReturnNothing};
+        use std::ffi::{OsStr, OsString};
+
+        #[test]
+        fn programs_without_override_are_kept() {
+            let cmd = Command::new("mapped-command-not-overridden", ReturnNothing);
+            assert_eq!(cmd.program(), "mapped-command-not-overridden");
+        }
+
+        #[test]
+        fn overrides_can_be_set_and_removed() {
+            set_program_override("mapped-command-set", "/opt/set");
+            let cmd = Command::new("mapped-command-set", ReturnNothing);
+            assert_eq!(cmd.program(), "/opt/set");
+
+            assert_eq!(
+                remove_program_override("mapped-command-set"),
+                Some("/opt/set".into())
+            );
+            let cmd = Command::new("mapped-command-set", ReturnNothing);
+            assert_eq!(cmd.program(), "mapped-command-set");
+        }
+
+        #[test]
+        fn env_variable_names_are_derived_from_the_program() {
+            assert_eq!(
+                program_override_env_var(OsStr::new("git-lfs.v2")),
+                "MAPPED_COMMAND_PROGRAM_GIT_LFS_V2"
+            );
+        }
+
+        #[test]
+        fn similar_program_names_share_the_env_variable() {
+            for program in &["git-lfs", "git_lfs", "git.lfs", "Git.LFS"] {
+                assert_eq!(
+                    program_override_env_var(OsStr::new(program)),
+                    "MAPPED_COMMAND_PROGRAM_GIT_LFS"
+                );
+            }
+
+            set_program_override("mapped-command-similar", "/opt/set");
+            let lookup_env = |_: &str| Some(OsString::from("/opt/env"));
+            assert_eq!(
+                lookup_program_override(OsStr::new("mapped_command_similar"), lookup_env),
+                Some("/opt/env".into())
+            );
+            assert_eq!(
+                lookup_program_override(OsStr::new("mapped-command-similar"), lookup_env),
+                Some("/opt/set".into())
+            );
+            remove_program_override("mapped-command-similar");
+        }
+
+        #[test]
+        fn overrides_can_be_set_through_the_env() {
+            let lookup_env = |name: &str| {
+                assert_eq!(name, "MAPPED_COMMAND_PROGRAM_MAPPED_COMMAND_ENV");
+                Some(OsString::from("/opt/env"))
+            };
+            assert_eq!(
+                lookup_program_override(OsStr::new("mapped-command-env"), lookup_env),
+                Some("/opt/env".into())
+            );
+            assert_eq!(
+                lookup_program_override(OsStr::new("mapped-command-env"), |_| Some("".into())),
+                None
+            );
+            assert_eq!(
+                lookup_program_override(OsStr::new("./mapped-command-env"), lookup_env),
+                None
+            );
+
+            set_program_override("mapped-command-env", "/opt/set");
+            assert_eq!(
+                lookup_program_override(OsStr::new("mapped-command-env"), lookup_env),
+                Some("/opt/set".into())
+            );
+            remove_program_override("mapped-command-env");
+        }
+    }
+}
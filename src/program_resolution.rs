@@ -0,0 +1,280 @@
+//! Which-style resolution of the program, see [`Command::resolve_program()`].
+use crate::{Command, UnexpectedExitStatus};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    io,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// The search path used if the sub-process has no `PATH` (like `execvp` does).
+#[cfg(unix)]
+const DEFAULT_SEARCH_PATH: &str = "/bin:/usr/bin";
+#[cfg(not(unix))]
+const DEFAULT_SEARCH_PATH: &str = "";
+
+impl<Output, Error> Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    /// Returns the path of the executable which will be run.
+    ///
+    /// Works like `which`, but uses the env and working directory of the sub-process:
+    ///
+    /// - If the program contains a path separator it's resolved relative to the
+    ///   [`Command::working_directory_override()`] (or the current working directory).
+    /// - Else the directories in the `PATH` the sub-process will have (see
+    ///   [`Command::create_expected_env_iter()`]) are searched in order, relative
+    ///   directories are resolved like above. If there is no `PATH` on unix `/bin:/usr/bin`
+    ///   is searched.
+    ///
+    /// On unix only files with at least one execute permission bit are considered, on
+    /// windows the extensions from `PATHEXT` are tried if the program has no extension.
+    ///
+    /// If [`Command::run()`] fails because the program wasn't found the returned
+    /// `io::Error` wraps the [`ProgramNotFound`] error returned by this method.
+    pub fn resolve_program(&self) -> Result<PathBuf, ProgramNotFound> {
+        let program = self.program();
+        let base_dir = self.resolution_base_dir();
+
+        if Path::new(program).components().count() > 1 {
+            let path = base_dir.join(program);
+            return find_executable(&path).ok_or_else(|| ProgramNotFound {
+                program: program.to_owned(),
+                searched_dirs: Vec::new(),
+            });
+        }
+
+        let search_path = self
+            .create_expected_env_iter()
+            .find(|(key, _)| is_path_var(key))
+            .map(|(_, value)| value.into_owned())
+            .unwrap_or_else(|| DEFAULT_SEARCH_PATH.into());
+
+        let mut searched_dirs = Vec::new();
+        for dir in env::split_paths(&search_path) {
+            let dir = base_dir.join(dir);
+            if let Some(path) = find_executable(&dir.join(program)) {
+                return Ok(path);
+            }
+            searched_dirs.push(dir);
+        }
+        Err(ProgramNotFound {
+            program: program.to_owned(),
+            searched_dirs,
+        })
+    }
+
+    /// Returns the directory relative paths are resolved against in the sub-process.
+    fn resolution_base_dir(&self) -> PathBuf {
+        let current_dir = env::current_dir().unwrap_or_default();
+        match self.working_directory_override() {
+            Some(dir) => current_dir.join(dir),
+            None => current_dir,
+        }
+    }
+}
+
+/// The program of a command could not be found, see [`Command::resolve_program()`].
+#[derive(Debug, Error)]
+#[error(
+    "Program {:?} not found{}",
+    program,
+    DisplaySearchedDirs(searched_dirs)
+)]
+pub struct ProgramNotFound {
+    program: OsString,
+    searched_dirs: Vec<PathBuf>,
+}
+
+impl ProgramNotFound {
+    /// The program which was not found.
+    pub fn program(&self) -> &OsStr {
+        &self.program
+    }
+
+    /// The directories from `PATH` which were searched, in order.
+    ///
+    /// This is empty if the program contains a path separator, in which case no
+    /// directories are searched.
+    pub fn searched_dirs(&self) -> &[PathBuf] {
+        &self.searched_dirs
+    }
+}
+
+impl From<ProgramNotFound> for io::Error {
+    fn from(err: ProgramNotFound) -> Self {
+        io::Error::new(io::ErrorKind::NotFound, err)
+    }
+}
+
+struct DisplaySearchedDirs<'a>(&'a [PathBuf]);
+
+impl std::fmt::Display for DisplaySearchedDirs<'_> {
+    fn fmt(&self, fter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut dirs = self.0.iter();
+        if let Some(first) = dirs.next() {
+            write!(fter, ", searched: {}", first.display())?;
+            for dir in dirs {
+                write!(fter, ", {}", dir.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(windows))]
+fn is_path_var(key: &OsStr) -> bool {
+    key == "PATH"
+}
+
+#[cfg(windows)]
+fn is_path_var(key: &OsStr) -> bool {
+    key.eq_ignore_ascii_case("PATH")
+}
+
+#[cfg(unix)]
+fn find_executable(path: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = path.metadata().ok()?;
+    if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
+        Some(path.to_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn find_executable(path: &Path) -> Option<PathBuf> {
+    if path.is_file() {
+        return Some(path.to_owned());
+    }
+    if path.extension().is_some() {
+        return None;
+    }
+    let extensions = env::var_os("PATHEXT").unwrap_or_else(|| ".COM;.EXE;.BAT;.CMD".into());
+    extensions
+        .to_str()
+        .unwrap_or_default()
+        .split(';')
+        .filter(|extension| !extension.is_empty())
+        .map(|extension| {
+            let mut path = path.as_os_str().to_owned();
+            path.push(extension);
+            PathBuf::from(path)
+        })
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    #[cfg(unix)]
+    mod resolve_program {
+        use crate::{Command, CommandExecutionError, ReturnNothing};
+        use std::{
+            env, fs,
+            os::unix::fs::PermissionsExt,
+            path::{Path, PathBuf},
+        };
+
+        fn temp_dir_with_program(name: &str, program: &str, mode: u32) -> PathBuf {
+            let dir =
+                env::temp_dir().join(format!("mapped-command-{}-{}", std::process::id(), name));
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(program);
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            dir
+        }
+
+        #[test]
+        fn uses_the_path_of_the_sub_process() {
+            let dir = temp_dir_with_program("path", "my-tool", 0o755);
+            let cmd = Command::new("my-tool", ReturnNothing)
+                .with_env_update("PATH", format!("/does/not/exist:{}", dir.display()));
+
+            let resolved = cmd.resolve_program();
+            fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!(resolved.unwrap(), dir.join("my-tool"));
+        }
+
+        #[test]
+        fn skips_files_which_are_not_executable() {
+            let dir = temp_dir_with_program("not-executable", "my-tool", 0o644);
+            let cmd =
+                Command::new("my-tool", ReturnNothing).with_env_update("PATH", dir.as_os_str());
+
+            let err = cmd.resolve_program().unwrap_err();
+            fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!(err.program(), "my-tool");
+            assert_eq!(err.searched_dirs(), &[dir]);
+        }
+
+        #[test]
+        fn relative_paths_are_resolved_against_the_working_directory_override() {
+            let dir = temp_dir_with_program("relative", "my-tool", 0o755);
+            let cmd = Command::new("./my-tool", ReturnNothing)
+                .with_working_directory_override(Some(&dir));
+            let resolved = cmd.resolve_program();
+            let cmd = Command::new("my-tool", ReturnNothing)
+                .with_env_update("PATH", "bin:.")
+                .with_working_directory_override(Some(&dir));
+            let resolved_from_path = cmd.resolve_program();
+            fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!(resolved.unwrap(), dir.join("./my-tool"));
+            assert_eq!(resolved_from_path.unwrap(), dir.join(".").join("my-tool"));
+        }
+
+        #[test]
+        fn running_a_missing_program_names_the_program_and_searched_dirs() {
+            let err = Command::new("mapped-command-missing-program", ReturnNothing)
+                .with_env_update("PATH", "/does/not/exist:/also/missing")
+                .run()
+                .unwrap_err();
+
+            match err {
+                CommandExecutionError::ProgramNotFound(err) => {
+                    assert_eq!(err.program(), "mapped-command-missing-program");
+                    assert_eq!(
+                        err.searched_dirs(),
+                        &[Path::new("/does/not/exist"), Path::new("/also/missing")]
+                    );
+                    assert_eq!(
+                        err.to_string(),
+                        "Program \"mapped-command-missing-program\" not found, searched: /does/not/exist, /also/missing"
+                    );
+                }
+                err => panic!("unexpected error: {:?}", err),
+            }
+        }
+
+        #[test]
+        fn missing_programs_are_reported_as_not_found_io_errors() {
+            let err = Command::new("/does/not/exist/program", ReturnNothing)
+                .with_exec_replacement_callback(|cmd, _| {
+                    Err(cmd.resolve_program().unwrap_err().into())
+                })
+                .run()
+                .unwrap_err();
+
+            match err {
+                CommandExecutionError::ProgramNotFound(err) => {
+                    assert!(err.searched_dirs().is_empty());
+                    assert_eq!(
+                        err.to_string(),
+                        "Program \"/does/not/exist/program\" not found"
+                    );
+                }
+                err => panic!("unexpected error: {:?}", err),
+            }
+        }
+    }
+}
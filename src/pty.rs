@@ -0,0 +1,73 @@
+//! Pseudo-terminal support used by [`Command::pty()`](crate::Command::pty).
+use crate::PtySize;
+use std::{
+    ffi::CStr,
+    fs::File,
+    io::{self, Read},
+    os::unix::io::{FromRawFd, OwnedFd},
+    thread,
+};
+
+/// Opens a new pseudo-terminal with given window size.
+///
+/// Returns the master side (for the current process) and the slave side
+/// (for the sub-process), both have the close-on-exec flag set.
+pub(crate) fn open(size: PtySize) -> Result<(File, OwnedFd), io::Error> {
+    // SAFETY: all calls get valid arguments and the returned fds are owned by us.
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+        if master == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(master);
+        let master_fd = std::os::unix::io::AsRawFd::as_raw_fd(&master);
+
+        if libc::grantpt(master_fd) == -1 || libc::unlockpt(master_fd) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut name = [0 as libc::c_char; 128];
+        let errno = libc::ptsname_r(master_fd, name.as_mut_ptr(), name.len());
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        let name = CStr::from_ptr(name.as_ptr());
+
+        let slave = libc::open(
+            name.as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        );
+        if slave == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let slave = OwnedFd::from_raw_fd(slave);
+
+        let window_size = libc::winsize {
+            ws_row: size.rows,
+            ws_col: size.cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        if libc::ioctl(master_fd, libc::TIOCSWINSZ, &window_size) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((master, slave))
+    }
+}
+
+/// Reads all output from the master side of a pseudo-terminal in a new thread.
+///
+/// Once all slave file descriptors are closed reading fails with `EIO` on linux,
+/// which is treated as the end of the output.
+pub(crate) fn spawn_read_output(
+    mut master: File,
+) -> thread::JoinHandle<Result<Vec<u8>, io::Error>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        match master.read_to_end(&mut buffer) {
+            Err(err) if err.raw_os_error() != Some(libc::EIO) => Err(err),
+            _ => Ok(buffer),
+        }
+    })
+}
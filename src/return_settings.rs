@@ -1,7 +1,7 @@
 use std::{io, string::FromUtf8Error};
 
 use super::OutputMapping;
-use crate::{ExitStatus, UnexpectedExitStatus};
+use crate::{ExitStatus, ProgramNotFound, UnexpectedExitStatus, ValidationError};
 use thiserror::Error;
 
 /// Error used by various [`OutputMapping`] implementations.
@@ -9,12 +9,37 @@ use thiserror::Error;
 pub enum CommandExecutionError {
     /// An io::Error happened, most likely because spawning failed.
     #[error(transparent)]
-    Io(#[from] io::Error),
+    Io(io::Error),
+    /// The program was not found, see [`Command::resolve_program()`].
+    ///
+    /// [`Command::resolve_program()`]: crate::Command::resolve_program
+    #[error(transparent)]
+    ProgramNotFound(#[from] ProgramNotFound),
+    /// The command is invalid, see [`Command::with_validate_before_spawn()`].
+    ///
+    /// [`Command::with_validate_before_spawn()`]: crate::Command::with_validate_before_spawn
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
     /// An unexpected exit status appeared.
     #[error(transparent)]
     UnexpectedExitStatus(#[from] UnexpectedExitStatus),
 }
 
+impl From<io::Error> for CommandExecutionError {
+    /// Converts `io::Error`s wrapping a [`ProgramNotFound`] or [`ValidationError`] error
+    /// into the dedicated variant.
+    fn from(err: io::Error) -> Self {
+        let err = match err.downcast() {
+            Ok(err) => return CommandExecutionError::ProgramNotFound(err),
+            Err(err) => err,
+        };
+        match err.downcast() {
+            Ok(err) => CommandExecutionError::Validation(err),
+            Err(err) => CommandExecutionError::Io(err),
+        }
+    }
+}
+
 /// Return `()` if the program successfully exits.
 #[derive(Debug)]
 pub struct ReturnNothing;
@@ -239,7 +264,18 @@ where
 pub enum CommandExecutionWithStringOutputError {
     /// Spawning failed or bad exit code.
     #[error(transparent)]
-    Io(#[from] io::Error),
+    Io(io::Error),
+
+    /// The program was not found, see [`Command::resolve_program()`].
+    ///
+    /// [`Command::resolve_program()`]: crate::Command::resolve_program
+    #[error(transparent)]
+    ProgramNotFound(#[from] ProgramNotFound),
+    /// The command is invalid, see [`Command::with_validate_before_spawn()`].
+    ///
+    /// [`Command::with_validate_before_spawn()`]: crate::Command::with_validate_before_spawn
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
 
     /// Run into an unexpected exit status.
     #[error(transparent)]
@@ -250,6 +286,21 @@ pub enum CommandExecutionWithStringOutputError {
     Utf8Error(#[from] FromUtf8Error),
 }
 
+impl From<io::Error> for CommandExecutionWithStringOutputError {
+    /// Converts `io::Error`s wrapping a [`ProgramNotFound`] or [`ValidationError`] error
+    /// into the dedicated variant.
+    fn from(err: io::Error) -> Self {
+        let err = match err.downcast() {
+            Ok(err) => return CommandExecutionWithStringOutputError::ProgramNotFound(err),
+            Err(err) => err,
+        };
+        match err.downcast() {
+            Ok(err) => CommandExecutionWithStringOutputError::Validation(err),
+            Err(err) => CommandExecutionWithStringOutputError::Io(err),
+        }
+    }
+}
+
 /// Map a stdout/err output (Vec<u8>) to an string.
 ///
 /// This is a thin wrapper around [`String::from_utf8()`] which
@@ -280,7 +331,7 @@ impl OutputMapping for ReturnStdoutString {
         _stderr: Option<Vec<u8>>,
         _exit_status: ExitStatus,
     ) -> Result<Self::Output, Self::Error> {
-        Ok(output_to_string(stdout.unwrap())?)
+        output_to_string(stdout.unwrap())
     }
 }
 
@@ -306,7 +357,7 @@ impl OutputMapping for ReturnStderrString {
         stderr: Option<Vec<u8>>,
         _exit_status: ExitStatus,
     ) -> Result<Self::Output, Self::Error> {
-        Ok(output_to_string(stderr.unwrap())?)
+        output_to_string(stderr.unwrap())
     }
 }
 
@@ -463,12 +514,12 @@ mod tests {
 
         #[test]
         fn captures_stdout_returns_true() {
-            assert_eq!(ReturnNothing.capture_stdout(), false);
+            assert!(!ReturnNothing.capture_stdout());
         }
 
         #[test]
         fn captures_stderr_returns_false() {
-            assert_eq!(ReturnNothing.capture_stderr(), false);
+            assert!(!ReturnNothing.capture_stderr());
         }
 
         #[test]
@@ -479,6 +530,7 @@ mod tests {
                         exit_status: 0.into(),
                         stdout: None,
                         stderr: None,
+                        ..Default::default()
                     })
                 })
                 .run()
@@ -493,12 +545,12 @@ mod tests {
 
         #[test]
         fn captures_stdout_returns_true() {
-            assert_eq!(ReturnStdout.capture_stdout(), true);
+            assert!(ReturnStdout.capture_stdout());
         }
 
         #[test]
         fn captures_stderr_returns_false() {
-            assert_eq!(ReturnStdout.capture_stderr(), false);
+            assert!(!ReturnStdout.capture_stderr());
         }
 
         proptest! {
@@ -512,7 +564,8 @@ mod tests {
                         Ok(ExecResult {
                             exit_status: 0.into(),
                             stdout: Some(stdout_),
-                            stderr: None
+                            stderr: None,
+                            ..Default::default()
                         })
                     })
                     .run()
@@ -530,12 +583,12 @@ mod tests {
 
         #[test]
         fn captures_stdout_returns_true() {
-            assert_eq!(ReturnStderr.capture_stdout(), false);
+            assert!(!ReturnStderr.capture_stdout());
         }
 
         #[test]
         fn captures_stderr_returns_false() {
-            assert_eq!(ReturnStderr.capture_stderr(), true);
+            assert!(ReturnStderr.capture_stderr());
         }
 
         proptest! {
@@ -549,7 +602,8 @@ mod tests {
                         Ok(ExecResult {
                             exit_status: 0.into(),
                             stdout: None,
-                            stderr: Some(stderr_)
+                            stderr: Some(stderr_),
+                            ..Default::default()
                         })
                     })
                     .run()
@@ -567,12 +621,12 @@ mod tests {
 
         #[test]
         fn captures_stdout_returns_true() {
-            assert_eq!(ReturnStdoutAndErr.capture_stdout(), true);
+            assert!(ReturnStdoutAndErr.capture_stdout());
         }
 
         #[test]
         fn captures_stderr_returns_false() {
-            assert_eq!(ReturnStdoutAndErr.capture_stderr(), true);
+            assert!(ReturnStdoutAndErr.capture_stderr());
         }
 
         proptest! {
@@ -588,7 +642,8 @@ mod tests {
                         Ok(ExecResult {
                             exit_status: 0.into(),
                             stdout: Some(stdout_),
-                            stderr: Some(stderr_)
+                            stderr: Some(stderr_),
+                            ..Default::default()
                         })
                     })
                     .run()
@@ -617,6 +672,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: Some("3241".into()),
                     stderr: None,
+                    ..Default::default()
                 })
             })
             .run()
@@ -638,6 +694,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: Some("abcd".into()),
                     stderr: None,
+                    ..Default::default()
                 })
             })
             .run()
@@ -662,6 +719,7 @@ mod tests {
                     exit_status: 0.into(),
                     stderr: Some("3241".into()),
                     stdout: None,
+                    ..Default::default()
                 })
             })
             .run()
@@ -683,6 +741,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: None,
                     stderr: Some("abcd".into()),
+                    ..Default::default()
                 })
             })
             .run()
@@ -709,6 +768,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: Some("3241".into()),
                     stderr: Some("1242".into()),
+                    ..Default::default()
                 })
             })
             .run()
@@ -730,6 +790,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: Some(Vec::new()),
                     stderr: Some(Vec::new()),
+                    ..Default::default()
                 })
             })
             .run()
@@ -738,11 +799,7 @@ mod tests {
     }
 
     fn is_utf8_error(err: &CommandExecutionWithStringOutputError) -> bool {
-        if let CommandExecutionWithStringOutputError::Utf8Error(_) = err {
-            true
-        } else {
-            false
-        }
+        matches!(err, CommandExecutionWithStringOutputError::Utf8Error(_))
     }
 
     #[test]
@@ -768,12 +825,12 @@ mod tests {
 
         #[test]
         fn captures_stdout_returns_true() {
-            assert_eq!(ReturnStdoutString.capture_stdout(), true);
+            assert!(ReturnStdoutString.capture_stdout());
         }
 
         #[test]
         fn captures_stderr_returns_false() {
-            assert_eq!(ReturnStdoutString.capture_stderr(), false);
+            assert!(!ReturnStdoutString.capture_stderr());
         }
 
         proptest! {
@@ -787,7 +844,8 @@ mod tests {
                         Ok(ExecResult {
                             exit_status: 0.into(),
                             stdout: Some(stdout_),
-                            stderr: None
+                            stderr: None,
+                            ..Default::default()
                         })
                     })
                     .run();
@@ -814,12 +872,12 @@ mod tests {
 
         #[test]
         fn captures_stdout_returns_true() {
-            assert_eq!(ReturnStderrString.capture_stdout(), false);
+            assert!(!ReturnStderrString.capture_stdout());
         }
 
         #[test]
         fn captures_stderr_returns_false() {
-            assert_eq!(ReturnStderrString.capture_stderr(), true);
+            assert!(ReturnStderrString.capture_stderr());
         }
 
         proptest! {
@@ -833,7 +891,8 @@ mod tests {
                         Ok(ExecResult {
                             exit_status: 0.into(),
                             stdout: None,
-                            stderr: Some(stderr_)
+                            stderr: Some(stderr_),
+                            ..Default::default()
                         })
                     })
                     .run();
@@ -860,12 +919,12 @@ mod tests {
 
         #[test]
         fn captures_stdout_returns_true() {
-            assert_eq!(ReturnStdoutAndErrStrings.capture_stdout(), true);
+            assert!(ReturnStdoutAndErrStrings.capture_stdout());
         }
 
         #[test]
         fn captures_stderr_returns_false() {
-            assert_eq!(ReturnStdoutAndErrStrings.capture_stderr(), true);
+            assert!(ReturnStdoutAndErrStrings.capture_stderr());
         }
 
         proptest! {
@@ -881,7 +940,8 @@ mod tests {
                         Ok(ExecResult {
                             exit_status: 0.into(),
                             stdout: Some(stdout_),
-                            stderr: Some(stderr_)
+                            stderr: Some(stderr_),
+                            ..Default::default()
                         })
                     })
                     .run();
@@ -920,6 +980,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: Some("3241".into()),
                     stderr: None,
+                    ..Default::default()
                 })
             })
             .run()
@@ -941,6 +1002,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: Some("abcd".into()),
                     stderr: None,
+                    ..Default::default()
                 })
             })
             .run()
@@ -965,6 +1027,7 @@ mod tests {
                     exit_status: 0.into(),
                     stderr: Some("3241".into()),
                     stdout: None,
+                    ..Default::default()
                 })
             })
             .run()
@@ -986,6 +1049,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: None,
                     stderr: Some("abcd".into()),
+                    ..Default::default()
                 })
             })
             .run()
@@ -1012,6 +1076,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: Some("3241".into()),
                     stderr: Some("1242".into()),
+                    ..Default::default()
                 })
             })
             .run()
@@ -1033,6 +1098,7 @@ mod tests {
                     exit_status: 0.into(),
                     stdout: Some(Vec::new()),
                     stderr: Some(Vec::new()),
+                    ..Default::default()
                 })
             })
             .run()
@@ -0,0 +1,194 @@
+//! Marking arguments and env values as secret, see [`Command::with_secret_argument()`].
+use crate::{Command, EnvChange, UnexpectedExitStatus};
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    io,
+};
+
+/// The value shown instead of secret values.
+pub(crate) const REDACTED: &str = "***";
+
+impl<Output, Error> Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    /// Returns this command with given secret argument added.
+    ///
+    /// The sub-process receives the argument as is, but everywhere the command is
+    /// rendered (e.g. its `Display` and `Debug` implementations or
+    /// [`ValidationProblem`]s) it's replaced with `***`.
+    ///
+    /// ```
+    /// # use mapped_command::{Command, ReturnNothing};
+    /// let cmd = Command::new("curl", ReturnNothing)
+    ///     .with_argument("-H")
+    ///     .with_secret_argument("Authorization: Bearer abcd")
+    ///     .with_argument("https://example.com");
+    /// assert_eq!(cmd.to_string(), "curl -H '***' https://example.com");
+    /// ```
+    ///
+    /// [`ValidationProblem`]: crate::ValidationProblem
+    pub fn with_secret_argument(mut self, arg: impl Into<OsString>) -> Self {
+        self.secret_arguments.insert(self.arguments.len());
+        self.with_argument(arg)
+    }
+
+    /// Returns true if the argument at given index was added as secret argument.
+    ///
+    /// See [`Command::with_secret_argument()`].
+    pub fn is_secret_argument(&self, index: usize) -> bool {
+        self.secret_arguments.contains(&index)
+    }
+
+    /// Returns this command with the env variable set to a secret value.
+    ///
+    /// This adds a [`EnvChange::Set`] env update, the sub-process receives the value as
+    /// is, but everywhere the command is rendered (e.g. its `Display` and `Debug`
+    /// implementations or [`Command::env_diff()`]) it's replaced with `***`.
+    ///
+    /// The variable stays marked as secret, even if it's later updated with e.g.
+    /// [`Command::with_env_update()`].
+    pub fn with_secret_env_update(
+        mut self,
+        key: impl Into<OsString>,
+        value: impl Into<OsString>,
+    ) -> Self {
+        let key = key.into();
+        self.secret_env_vars.insert(key.clone());
+        self.with_env_update(key, EnvChange::Set(value.into()))
+    }
+
+    /// Returns true if the value of given env variable was marked as secret.
+    ///
+    /// See [`Command::with_secret_env_update()`].
+    pub fn is_secret_env_var(&self, key: &OsStr) -> bool {
+        self.secret_env_vars.contains(key)
+    }
+
+    /// Returns the arguments with secret arguments replaced by `***`.
+    pub(crate) fn redacted_arguments(&self) -> impl Iterator<Item = &OsStr> {
+        self.arguments()
+            .iter()
+            .enumerate()
+            .map(move |(index, argument)| {
+                if self.is_secret_argument(index) {
+                    OsStr::new(REDACTED)
+                } else {
+                    argument
+                }
+            })
+    }
+
+    /// Returns given env change with the values of secret variables replaced by `***`.
+    pub(crate) fn redacted_env_change<'a>(
+        &self,
+        key: &OsStr,
+        change: &'a EnvChange,
+    ) -> Cow<'a, EnvChange> {
+        if !self.is_secret_env_var(key) {
+            return Cow::Borrowed(change);
+        }
+        Cow::Owned(match change {
+            EnvChange::Set(_) => EnvChange::Set(REDACTED.into()),
+            EnvChange::Prepend(_) => EnvChange::Prepend(REDACTED.into()),
+            EnvChange::Append(_) => EnvChange::Append(REDACTED.into()),
+            EnvChange::Remove => EnvChange::Remove,
+            EnvChange::Inherit => EnvChange::Inherit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod Command {
+        use crate::{Command, EnvChange, ExecResult, ReturnNothing, ValidationProblem};
+        use std::ffi::OsStr;
+
+        #[test]
+        fn secret_arguments_are_passed_as_is() {
+            let cmd = Command::new("foo", ReturnNothing)
+                .with_argument("a")
+                .with_secret_argument("secret")
+                .with_arguments(["b"]);
+
+            assert_eq!(cmd.arguments(), &["a", "secret", "b"]);
+            assert!(!cmd.is_secret_argument(0));
+            assert!(cmd.is_secret_argument(1));
+            assert!(!cmd.is_secret_argument(2));
+
+            cmd.with_exec_replacement_callback(|cmd, _| {
+                assert_eq!(cmd.arguments()[1], "secret");
+                Ok(ExecResult::default())
+            })
+            .run()
+            .unwrap();
+        }
+
+        #[test]
+        fn secret_env_values_are_passed_as_is() {
+            let cmd = Command::new("foo", ReturnNothing)
+                .with_secret_env_update("TOKEN", "abcd")
+                .with_env_update("OTHER", "x");
+
+            assert!(cmd.is_secret_env_var(OsStr::new("TOKEN")));
+            assert!(!cmd.is_secret_env_var(OsStr::new("OTHER")));
+            assert_eq!(
+                cmd.env_updates().get(OsStr::new("TOKEN")),
+                Some(&EnvChange::Set("abcd".into()))
+            );
+            assert!(cmd
+                .create_expected_env_iter()
+                .any(|(key, value)| *key == *"TOKEN" && *value == *"abcd"));
+        }
+
+        #[test]
+        fn secrets_are_redacted_in_display_and_debug() {
+            let cmd = Command::new("foo", ReturnNothing)
+                .with_secret_argument("hunter2")
+                .with_secret_env_update("MY_VALUE", "hunter3")
+                .with_env_update("PATH", EnvChange::Prepend("/opt/bin".into()));
+
+            let display = cmd.to_string();
+            assert!(!display.contains("hunter"), "{}", display);
+            assert!(display.starts_with("MY_VALUE='***' "), "{}", display);
+            assert!(display.ends_with(" foo '***'"), "{}", display);
+
+            let debug = format!("{:?}", cmd);
+            assert!(!debug.contains("hunter"), "{}", debug);
+            assert!(debug.contains(r#"arguments: ["***"]"#), "{}", debug);
+            assert!(debug.contains(r#""MY_VALUE": Set("***")"#), "{}", debug);
+            assert!(
+                debug.contains(r#""PATH": Prepend("/opt/bin")"#),
+                "{}",
+                debug
+            );
+        }
+
+        #[test]
+        fn secrets_are_redacted_in_the_env_diff() {
+            let diff = Command::new("foo", ReturnNothing)
+                .with_secret_env_update("MY_VALUE", "hunter2")
+                .env_diff();
+            assert_eq!(
+                diff.added().get(OsStr::new("MY_VALUE")),
+                Some(&"***".into())
+            );
+        }
+
+        #[test]
+        fn secrets_are_redacted_in_validation_problems() {
+            let err = Command::new("/does/not/exist", ReturnNothing)
+                .with_secret_argument("hunter\0")
+                .validate()
+                .unwrap_err();
+            assert!(matches!(
+                &err.problems()[1],
+                ValidationProblem::ArgumentContainsNul { index: 0, argument } if argument == "***"
+            ));
+        }
+    }
+}
@@ -0,0 +1,611 @@
+//! Expect-style interactive sessions running a [`Command`] in a pseudo-terminal.
+use crate::{
+    sys::{self, Spawned},
+    Command, EnvDiff, ExitStatus, PtySize, UnexpectedExitStatus,
+};
+use regex::bytes::Regex;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read, Write},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+impl<Output, Error> Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    /// Returns the script used to mock interactive sessions, if any.
+    ///
+    /// If set [`Command::spawn_session()`] doesn't spawn the program but
+    /// returns a [`Session`] which plays given script instead.
+    ///
+    /// *This is only available on linux.*
+    pub fn session_script(&self) -> Option<&SessionScript> {
+        self.unix_settings.session_script.as_ref()
+    }
+
+    /// Sets the script used to mock interactive sessions.
+    ///
+    /// See [`Command::session_script()`].
+    pub fn with_session_script(mut self, script: Option<SessionScript>) -> Self {
+        self.unix_settings.session_script = script;
+        self
+    }
+
+    /// Spawns the command as an interactive [`Session`].
+    ///
+    /// The sub-process runs in a pseudo-terminal (see [`Command::pty()`]), if no
+    /// window size was set the [`PtySize::default()`] is used. All other settings
+    /// apply as for [`Command::run()`], except that the output mapping is not used.
+    /// The exit status is checked by [`Session::expect_eof()`].
+    ///
+    /// If a [`Command::session_script()`] is set the script is played instead of
+    /// spawning the program.
+    ///
+    /// *This is only available on linux.*
+    pub fn spawn_session(mut self) -> Result<Session, io::Error> {
+        let expected_exit_status = self.expected_exit_status();
+        let check_exit_status = self.check_exit_status();
+        let env_diff = if check_exit_status && self.env_diff_in_errors() {
+            Some(Box::new(self.env_diff()))
+        } else {
+            None
+        };
+
+        let backend = if let Some(script) = self.unix_settings.session_script.take() {
+            Backend::Scripted {
+                steps: script.steps.into(),
+            }
+        } else {
+            if self.pty().is_none() {
+                self = self.with_pty(Some(PtySize::default()));
+            }
+            let mut spawned = sys::spawn(&self, false, false)?;
+            let master = spawned
+                .pty_master
+                .take()
+                .expect("a pty is always used for sessions");
+            let output = spawn_forward_output(master.try_clone()?);
+            Backend::Process {
+                spawned,
+                input: master,
+                output,
+            }
+        };
+
+        Ok(Session {
+            backend,
+            buffer: Vec::new(),
+            transcript: Vec::new(),
+            check_exit_status,
+            expected_exit_status,
+            env_diff,
+        })
+    }
+}
+
+/// An interactive session with a sub-process, see [`Command::spawn_session()`].
+///
+/// Dropping the session kills the sub-process if it's still running.
+pub struct Session {
+    backend: Backend,
+    /// Output which was received but not yet consumed by an `expect` call.
+    buffer: Vec<u8>,
+    /// All output received so far.
+    transcript: Vec<u8>,
+    check_exit_status: bool,
+    expected_exit_status: ExitStatus,
+    env_diff: Option<Box<EnvDiff>>,
+}
+
+enum Backend {
+    Process {
+        spawned: Spawned,
+        input: File,
+        output: Receiver<Result<Vec<u8>, io::Error>>,
+    },
+    Scripted {
+        steps: VecDeque<ScriptStep>,
+    },
+}
+
+/// The result of a successful [`Session::expect()`] call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectMatch {
+    /// The output between the end of the previous match and the start of this match.
+    pub before: String,
+    /// The matched output.
+    pub matched: String,
+    /// The capture groups of the match, the group `0` is the whole match.
+    pub captures: Vec<Option<String>>,
+}
+
+impl Session {
+    /// Waits until the output matches given regex.
+    ///
+    /// The regex is matched against the output received since the end of the
+    /// previous match, all output up to the end of this match is consumed. A bytes
+    /// regex is used as the output might not be valid UTF-8 and might end in the
+    /// middle of a character.
+    ///
+    /// Be aware that the terminal echos the input sent to it, so the input also
+    /// appears in the output.
+    ///
+    /// Fails if the output doesn't match within given timeout or if the output
+    /// ends before it matches.
+    pub fn expect(
+        &mut self,
+        regex: &Regex,
+        timeout: Duration,
+    ) -> Result<ExpectMatch, SessionError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(captures) = regex.captures(&self.buffer) {
+                let whole = captures.get(0).expect("group 0 always exists");
+                let to_string = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+                let expect_match = ExpectMatch {
+                    before: to_string(&self.buffer[..whole.start()]),
+                    matched: to_string(whole.as_bytes()),
+                    captures: captures
+                        .iter()
+                        .map(|group| group.map(|group| to_string(group.as_bytes())))
+                        .collect(),
+                };
+                self.buffer.drain(..whole.end());
+                return Ok(expect_match);
+            }
+
+            match self.receive(deadline) {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Err(SessionError::Eof {
+                        waiting_for: regex.as_str().to_owned(),
+                        transcript: self.transcript(),
+                    })
+                }
+                Err(Received::Timeout) => {
+                    return Err(SessionError::Timeout {
+                        waiting_for: regex.as_str().to_owned(),
+                        timeout,
+                        transcript: self.transcript(),
+                    })
+                }
+                Err(Received::Io(source)) => return Err(self.io_error(source)),
+            }
+        }
+    }
+
+    /// Sends given line (with a trailing newline) to the sub-process.
+    pub fn send_line(&mut self, line: &str) -> Result<(), SessionError> {
+        match &mut self.backend {
+            Backend::Process { input, .. } => {
+                let result = input
+                    .write_all(line.as_bytes())
+                    .and_then(|()| input.write_all(b"\n"))
+                    .and_then(|()| input.flush());
+                result.map_err(|source| self.io_error(source))
+            }
+            Backend::Scripted { steps } => {
+                // Output written before the program waits for input is
+                // received, like it would be when running the program.
+                while let Some(ScriptStep::Output(output)) = steps.front() {
+                    self.buffer.extend_from_slice(output.as_bytes());
+                    self.transcript.extend_from_slice(output.as_bytes());
+                    steps.pop_front();
+                }
+                match steps.pop_front() {
+                    Some(ScriptStep::InputLine(expected)) if expected == line => Ok(()),
+                    other => {
+                        let expected = match other {
+                            Some(ScriptStep::InputLine(expected)) => Some(expected),
+                            _ => None,
+                        };
+                        Err(SessionError::UnexpectedInput {
+                            expected,
+                            got: line.to_owned(),
+                            transcript: self.transcript(),
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits until the output ends and the sub-process exits.
+    ///
+    /// Like [`Command::run()`] this fails if the exit status is not the expected
+    /// one, except if exit status checking is disabled for the command.
+    pub fn expect_eof(&mut self, timeout: Duration) -> Result<ExitStatus, SessionError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.receive(deadline) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(Received::Timeout) => {
+                    return Err(SessionError::Timeout {
+                        waiting_for: "EOF".to_owned(),
+                        timeout,
+                        transcript: self.transcript(),
+                    })
+                }
+                Err(Received::Io(source)) => return Err(self.io_error(source)),
+            }
+        }
+
+        let exit_status = match &mut self.backend {
+            Backend::Process { spawned, .. } => {
+                let timeout_left = deadline.saturating_duration_since(Instant::now());
+                match spawned.child.wait_timeout(Some(timeout_left)) {
+                    Ok(Some(status)) => sys::map_std_exit_status(status),
+                    Ok(None) => {
+                        return Err(SessionError::Timeout {
+                            waiting_for: "EOF".to_owned(),
+                            timeout,
+                            transcript: self.transcript(),
+                        })
+                    }
+                    Err(source) => return Err(self.io_error(source)),
+                }
+            }
+            Backend::Scripted { steps } => match steps.pop_front() {
+                Some(ScriptStep::Exit(exit_status)) => exit_status,
+                _ => ExitStatus::Code(0),
+            },
+        };
+
+        let forwarded_signals = match &self.backend {
+            Backend::Process { spawned, .. } => spawned.child.forwarded_signals().to_owned(),
+            Backend::Scripted { .. } => Vec::new(),
+        };
+        if self.check_exit_status && exit_status != self.expected_exit_status {
+            Err(SessionError::UnexpectedExitStatus {
+                source: UnexpectedExitStatus {
+                    got: exit_status,
+                    expected: self.expected_exit_status,
+                    forwarded_signals,
+                    env_diff: self.env_diff.clone(),
+                },
+                transcript: self.transcript(),
+            })
+        } else {
+            Ok(exit_status)
+        }
+    }
+
+    /// Returns all output received so far.
+    ///
+    /// Invalid UTF-8 is replaced with `U+FFFD`.
+    pub fn transcript(&self) -> String {
+        String::from_utf8_lossy(&self.transcript).into_owned()
+    }
+
+    /// Receives more output, returns `false` if the output ended.
+    fn receive(&mut self, deadline: Instant) -> Result<bool, Received> {
+        let output = match &mut self.backend {
+            Backend::Process { output, .. } => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match output.recv_timeout(timeout) {
+                    Ok(Ok(output)) => output,
+                    Ok(Err(err)) => return Err(Received::Io(err)),
+                    Err(RecvTimeoutError::Timeout) => return Err(Received::Timeout),
+                    Err(RecvTimeoutError::Disconnected) => return Ok(false),
+                }
+            }
+            Backend::Scripted { steps } => match steps.front() {
+                Some(ScriptStep::Output(_)) => match steps.pop_front() {
+                    Some(ScriptStep::Output(output)) => output.into_bytes(),
+                    _ => unreachable!(),
+                },
+                // A program waiting for input produces no output, so waiting would time out.
+                Some(ScriptStep::InputLine(_)) => return Err(Received::Timeout),
+                Some(ScriptStep::Exit(_)) | None => return Ok(false),
+            },
+        };
+        self.buffer.extend_from_slice(&output);
+        self.transcript.extend_from_slice(&output);
+        Ok(true)
+    }
+
+    fn io_error(&self, source: io::Error) -> SessionError {
+        SessionError::Io {
+            source,
+            transcript: self.transcript(),
+        }
+    }
+}
+
+enum Received {
+    Timeout,
+    Io(io::Error),
+}
+
+/// Reads the output of the pseudo-terminal in a new thread and sends it through a channel.
+///
+/// The channel disconnects once the output ended.
+fn spawn_forward_output(mut master: File) -> Receiver<Result<Vec<u8>, io::Error>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let result = match master.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(read) => Ok(buffer[..read].to_vec()),
+                // Once all slave fds are closed reading fails with `EIO` on linux.
+                Err(err) if err.raw_os_error() == Some(libc::EIO) => return,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => Err(err),
+            };
+            let failed = result.is_err();
+            if sender.send(result).is_err() || failed {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+/// Interacting with a [`Session`] failed.
+///
+/// All variants contain the transcript of the output received until the failure.
+#[derive(Debug, Error)]
+pub enum SessionError {
+    /// The expected output didn't appear in time.
+    #[error("Timed out after {timeout:?} waiting for {waiting_for:?}. Transcript: {transcript:?}")]
+    Timeout {
+        waiting_for: String,
+        timeout: Duration,
+        transcript: String,
+    },
+
+    /// The output ended before the expected output appeared.
+    #[error("Output ended while waiting for {waiting_for:?}. Transcript: {transcript:?}")]
+    Eof {
+        waiting_for: String,
+        transcript: String,
+    },
+
+    /// The sub-process exited with an unexpected exit status.
+    #[error("{source}. Transcript: {transcript:?}")]
+    UnexpectedExitStatus {
+        source: UnexpectedExitStatus,
+        transcript: String,
+    },
+
+    /// A [`SessionScript`] didn't expect the sent input.
+    #[error("Unexpected input {got:?}, expected {expected:?}. Transcript: {transcript:?}")]
+    UnexpectedInput {
+        expected: Option<String>,
+        got: String,
+        transcript: String,
+    },
+
+    /// Reading from or writing to the sub-process failed.
+    #[error("{source}. Transcript: {transcript:?}")]
+    Io {
+        source: io::Error,
+        transcript: String,
+    },
+}
+
+impl SessionError {
+    /// Returns the transcript of the output received until the failure.
+    pub fn transcript(&self) -> &str {
+        match self {
+            SessionError::Timeout { transcript, .. }
+            | SessionError::Eof { transcript, .. }
+            | SessionError::UnexpectedExitStatus { transcript, .. }
+            | SessionError::UnexpectedInput { transcript, .. }
+            | SessionError::Io { transcript, .. } => transcript,
+        }
+    }
+}
+
+/// A script mocking an interactive program, see [`Command::with_session_script()`].
+///
+/// The steps are played in order: output is received by [`Session::expect()`],
+/// input lines must be sent with [`Session::send_line()`] and once the script
+/// ends (or reaches [`SessionScript::with_exit()`]) the output ends.
+///
+/// Waiting for output while the script waits for input fails immediately with
+/// [`SessionError::Timeout`]. Terminal specifics like echoing the input or
+/// `"\r\n"` line endings are not emulated.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionScript {
+    steps: Vec<ScriptStep>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ScriptStep {
+    Output(String),
+    InputLine(String),
+    Exit(ExitStatus),
+}
+
+impl SessionScript {
+    /// Creates an empty script.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns this script with an step outputting given text.
+    pub fn with_output(mut self, output: impl Into<String>) -> Self {
+        self.steps.push(ScriptStep::Output(output.into()));
+        self
+    }
+
+    /// Returns this script with an step waiting for given input line (without newline).
+    pub fn with_input_line(mut self, line: impl Into<String>) -> Self {
+        self.steps.push(ScriptStep::InputLine(line.into()));
+        self
+    }
+
+    /// Returns this script with an step exiting with given exit status.
+    ///
+    /// Steps after this are ignored. If a script has no exit step it exits with
+    /// exit code `0` once all steps are played.
+    pub fn with_exit(mut self, exit_status: ExitStatus) -> Self {
+        self.steps.push(ScriptStep::Exit(exit_status));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod Session {
+        use crate::{Command, ExitStatus, ReturnNothing, SessionError, SessionScript};
+        use regex::bytes::{Regex, RegexBuilder};
+        use std::time::Duration;
+
+        const TIMEOUT: Duration = Duration::from_secs(10);
+
+        fn regex(pattern: &str) -> Regex {
+            Regex::new(pattern).unwrap()
+        }
+
+        #[test]
+        fn interacts_with_a_program_in_a_pty() {
+            let mut session = Command::new("sh", ReturnNothing)
+                .with_arguments([
+                    "-c",
+                    "test -t 0 && printf 'Name? '; read name; echo \"Hello $name!\"",
+                ])
+                .spawn_session()
+                .unwrap();
+
+            session.expect(&regex(r"Name\? "), TIMEOUT).unwrap();
+            session.send_line("Alice").unwrap();
+            let found = session.expect(&regex(r"Hello (\w+)!"), TIMEOUT).unwrap();
+            assert_eq!(found.captures[1].as_deref(), Some("Alice"));
+            assert_eq!(found.before, "Alice\r\n");
+            assert_eq!(session.expect_eof(TIMEOUT).unwrap(), ExitStatus::Code(0));
+        }
+
+        #[test]
+        fn timeouts_include_the_transcript() {
+            let mut session = Command::new("sh", ReturnNothing)
+                .with_arguments(["-c", "echo hy; sleep 10"])
+                .spawn_session()
+                .unwrap();
+
+            let err = session
+                .expect(&regex("never"), Duration::from_millis(200))
+                .unwrap_err();
+            assert!(matches!(err, SessionError::Timeout { .. }), "{:?}", err);
+            assert_eq!(err.transcript(), "hy\r\n");
+        }
+
+        #[test]
+        fn unexpected_exit_status_fails_expect_eof() {
+            let mut session = Command::new("sh", ReturnNothing)
+                .with_arguments(["-c", "echo failing; exit 3"])
+                .spawn_session()
+                .unwrap();
+
+            let err = session.expect(&regex("never"), TIMEOUT).unwrap_err();
+            assert!(matches!(err, SessionError::Eof { .. }), "{:?}", err);
+            let err = session.expect_eof(TIMEOUT).unwrap_err();
+            assert!(
+                matches!(err, SessionError::UnexpectedExitStatus { .. }),
+                "{:?}",
+                err
+            );
+            assert_eq!(err.transcript(), "failing\r\n");
+        }
+
+        #[test]
+        fn plays_a_session_script() {
+            let script = SessionScript::new()
+                .with_output("Continue? [y/n] ")
+                .with_input_line("y")
+                .with_output("done\n")
+                .with_exit(ExitStatus::Code(0));
+            let mut session = Command::new("installer", ReturnNothing)
+                .with_session_script(Some(script))
+                .spawn_session()
+                .unwrap();
+
+            session.expect(&regex(r"\[y/n\] $"), TIMEOUT).unwrap();
+            session.send_line("y").unwrap();
+            session.expect(&regex("done"), TIMEOUT).unwrap();
+            assert_eq!(session.expect_eof(TIMEOUT).unwrap(), ExitStatus::Code(0));
+            assert_eq!(session.transcript(), "Continue? [y/n] done\n");
+        }
+
+        #[test]
+        fn regex_builder_flags_are_used() {
+            let script = SessionScript::new().with_output("DONE\n");
+            let mut session = Command::new("installer", ReturnNothing)
+                .with_session_script(Some(script))
+                .spawn_session()
+                .unwrap();
+
+            let regex = RegexBuilder::new("^done$")
+                .case_insensitive(true)
+                .multi_line(true)
+                .build()
+                .unwrap();
+            let found = session.expect(&regex, TIMEOUT).unwrap();
+            assert_eq!(found.matched, "DONE");
+        }
+
+        #[test]
+        fn session_scripts_detect_unexpected_input() {
+            let script = SessionScript::new()
+                .with_output("Continue? ")
+                .with_input_line("y");
+            let mut session = Command::new("installer", ReturnNothing)
+                .with_session_script(Some(script))
+                .spawn_session()
+                .unwrap();
+
+            let err = session.send_line("n").unwrap_err();
+            match err {
+                SessionError::UnexpectedInput {
+                    expected,
+                    got,
+                    transcript,
+                } => {
+                    assert_eq!(expected.as_deref(), Some("y"));
+                    assert_eq!(got, "n");
+                    assert_eq!(transcript, "Continue? ");
+                }
+                other => panic!("unexpected error: {:?}", other),
+            }
+        }
+
+        #[test]
+        fn session_scripts_time_out_while_waiting_for_input() {
+            let script = SessionScript::new().with_input_line("y");
+            let mut session = Command::new("installer", ReturnNothing)
+                .with_session_script(Some(script))
+                .spawn_session()
+                .unwrap();
+
+            let err = session.expect(&regex("never"), TIMEOUT).unwrap_err();
+            assert!(matches!(err, SessionError::Timeout { .. }), "{:?}", err);
+        }
+
+        #[test]
+        fn session_scripts_check_the_exit_status() {
+            let script = SessionScript::new().with_exit(ExitStatus::Code(1));
+            let mut session = Command::new("installer", ReturnNothing)
+                .with_session_script(Some(script))
+                .spawn_session()
+                .unwrap();
+
+            let err = session.expect_eof(TIMEOUT).unwrap_err();
+            assert!(
+                matches!(err, SessionError::UnexpectedExitStatus { .. }),
+                "{:?}",
+                err
+            );
+        }
+    }
+}
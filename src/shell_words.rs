@@ -0,0 +1,545 @@
+//! Creating commands from shell-like strings, see [`Command::from_shell_words()`].
+use crate::{Command, OutputMapping, UnexpectedExitStatus};
+use std::{collections::HashMap, io, iter::Peekable, str::Chars};
+use thiserror::Error;
+
+impl<Output, Error> Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    /// Creates a new command from a shell-like command line.
+    ///
+    /// The input is split into words using the POSIX shell quoting rules, the first word
+    /// is the program and the others are the arguments. The input is never run by a
+    /// shell:
+    ///
+    /// - words are separated by unquoted spaces, tabs and newlines
+    /// - `'...'` quotes everything literally
+    /// - `"..."` quotes everything except `\` escaping `$`, `` ` ``, `"`, `\` and newlines
+    /// - an unquoted `\` escapes the next character, `\` followed by a newline is removed
+    /// - an unquoted `#` at the start of a word starts a comment till the end of the line
+    /// - `$` is taken literally, see [`Command::from_shell_words_with_vars()`] for expansion
+    /// - globs (e.g. `*`) and `~` are taken literally
+    /// - unquoted operators (`|`, `&`, `;`, `<`, `>`, `(`, `)`) and `` ` `` are rejected
+    ///
+    /// ```
+    /// # use mapped_command::{Command, ReturnNothing};
+    /// let cmd = Command::from_shell_words(r#"rsync -az --delete "my dir/" 'dest dir/'"#, ReturnNothing)?;
+    /// assert_eq!(cmd.program(), "rsync");
+    /// assert_eq!(cmd.arguments(), &["-az", "--delete", "my dir/", "dest dir/"]);
+    /// # Ok::<(), mapped_command::ShellWordsError>(())
+    /// ```
+    ///
+    /// Fails if the input has unbalanced quotes, unsupported syntax or no words at all,
+    /// errors contain the line and column of the problem.
+    pub fn from_shell_words(
+        input: &str,
+        return_settings: impl OutputMapping<Output = Output, Error = Error>,
+    ) -> Result<Self, ShellWordsError> {
+        Self::from_words(split(input, None)?, return_settings)
+    }
+
+    /// Like [`Command::from_shell_words()`] but with `$VAR` and `${VAR}` expanded.
+    ///
+    /// The values are looked up in `vars`, unquoted and double quoted variables are
+    /// expanded. Unlike in a shell the values are never split into multiple words, so
+    /// `$SRC` is a single argument even if the value contains whitespace. A `$` not
+    /// followed by a variable name is taken literally. Positional and special parameters
+    /// (`$1`, `$@`, `$?`, ...), parameter expansions like `${VAR:-default}`, command
+    /// substitutions (`$(...)`) and arithmetic expansions (`$((...))`) are rejected as
+    /// unsupported syntax.
+    ///
+    /// ```
+    /// # use mapped_command::{Command, ReturnNothing};
+    /// # use std::collections::HashMap;
+    /// let mut vars = HashMap::new();
+    /// vars.insert("SRC".to_owned(), "my dir/".to_owned());
+    /// let cmd = Command::from_shell_words_with_vars(
+    ///     r#"rsync -az --delete "$SRC" dest/"#,
+    ///     &vars,
+    ///     ReturnNothing,
+    /// )?;
+    /// assert_eq!(cmd.arguments(), &["-az", "--delete", "my dir/", "dest/"]);
+    /// # Ok::<(), mapped_command::ShellWordsError>(())
+    /// ```
+    ///
+    /// Fails like [`Command::from_shell_words()`] and if a variable isn't in `vars`.
+    pub fn from_shell_words_with_vars(
+        input: &str,
+        vars: &HashMap<String, String>,
+        return_settings: impl OutputMapping<Output = Output, Error = Error>,
+    ) -> Result<Self, ShellWordsError> {
+        Self::from_words(split(input, Some(vars))?, return_settings)
+    }
+
+    fn from_words(
+        words: Vec<String>,
+        return_settings: impl OutputMapping<Output = Output, Error = Error>,
+    ) -> Result<Self, ShellWordsError> {
+        let mut words = words.into_iter();
+        let program = words.next().ok_or(ShellWordsError::Empty)?;
+        Ok(Command::new(program, return_settings).with_arguments(words))
+    }
+}
+
+/// Parsing a shell-like command line failed, see [`Command::from_shell_words()`].
+///
+/// Lines and columns start at 1.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ShellWordsError {
+    /// A quote is not closed, the position is the one of the opening quote.
+    #[error("Unterminated {quote} quote (line {line}, column {column})")]
+    UnterminatedQuote {
+        /// The quote character, `'` or `"`.
+        quote: char,
+        /// The line of the opening quote.
+        line: usize,
+        /// The column of the opening quote.
+        column: usize,
+    },
+
+    /// A `${` is not closed, the position is the one of the `$`.
+    #[error("Unterminated '${{' (line {line}, column {column})")]
+    UnterminatedVariable {
+        /// The line of the `$`.
+        line: usize,
+        /// The column of the `$`.
+        column: usize,
+    },
+
+    /// The input ends with an unquoted `\`.
+    #[error("Backslash at the end of the input (line {line}, column {column})")]
+    TrailingBackslash {
+        /// The line of the backslash.
+        line: usize,
+        /// The column of the backslash.
+        column: usize,
+    },
+
+    /// A variable isn't in the map passed to [`Command::from_shell_words_with_vars()`].
+    #[error("Undefined variable {name:?} (line {line}, column {column})")]
+    UndefinedVariable {
+        /// The name of the variable.
+        name: String,
+        /// The line of the `$`.
+        line: usize,
+        /// The column of the `$`.
+        column: usize,
+    },
+
+    /// The input contains shell syntax which isn't supported, like pipes or redirections.
+    #[error("Unsupported shell syntax {found:?} (line {line}, column {column})")]
+    UnsupportedSyntax {
+        /// The first character of the unsupported syntax.
+        found: char,
+        /// The line of the character.
+        line: usize,
+        /// The column of the character.
+        column: usize,
+    },
+
+    /// The input contains no words.
+    #[error("No program given")]
+    Empty,
+}
+
+/// Splits the input into words, expands variables if `vars` is given.
+fn split(
+    input: &str,
+    vars: Option<&HashMap<String, String>>,
+) -> Result<Vec<String>, ShellWordsError> {
+    let mut parser = Parser {
+        chars: input.chars().peekable(),
+        line: 1,
+        column: 1,
+        vars,
+    };
+    let mut words = Vec::new();
+    loop {
+        parser.take_while(is_blank);
+        match parser.peek() {
+            None => return Ok(words),
+            Some('#') => {
+                parser.take_while(|char| char != '\n');
+            }
+            Some(_) => words.push(parser.parse_word()?),
+        }
+    }
+}
+
+/// Returns true for the characters separating words, unlike `char::is_whitespace()`
+/// this doesn't include e.g. carriage returns or non-breaking spaces.
+fn is_blank(char: char) -> bool {
+    matches!(char, ' ' | '\t' | '\n')
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    vars: Option<&'a HashMap<String, String>>,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let char = self.chars.next()?;
+        if char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(char)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(char) = self.peek().filter(|&char| predicate(char)) {
+            self.next();
+            taken.push(char);
+        }
+        taken
+    }
+
+    fn parse_word(&mut self) -> Result<String, ShellWordsError> {
+        let mut word = String::new();
+        loop {
+            let (line, column) = (self.line, self.column);
+            match self.peek() {
+                None => return Ok(word),
+                Some(char) if is_blank(char) => return Ok(word),
+                Some(found @ ('|' | '&' | ';' | '<' | '>' | '(' | ')' | '`')) => {
+                    return Err(ShellWordsError::UnsupportedSyntax {
+                        found,
+                        line,
+                        column,
+                    })
+                }
+                Some('\\') => {
+                    self.next();
+                    match self.next() {
+                        None => return Err(ShellWordsError::TrailingBackslash { line, column }),
+                        Some('\n') => {}
+                        Some(char) => word.push(char),
+                    }
+                }
+                Some('\'') => {
+                    self.next();
+                    word.push_str(&self.take_while(|char| char != '\''));
+                    if self.next().is_none() {
+                        return Err(ShellWordsError::UnterminatedQuote {
+                            quote: '\'',
+                            line,
+                            column,
+                        });
+                    }
+                }
+                Some('"') => self.parse_double_quoted(&mut word)?,
+                Some('$') if self.vars.is_some() => self.parse_variable(&mut word)?,
+                Some(char) => {
+                    self.next();
+                    word.push(char);
+                }
+            }
+        }
+    }
+
+    fn parse_double_quoted(&mut self, word: &mut String) -> Result<(), ShellWordsError> {
+        let unterminated = ShellWordsError::UnterminatedQuote {
+            quote: '"',
+            line: self.line,
+            column: self.column,
+        };
+        self.next();
+        loop {
+            match self.peek() {
+                None => return Err(unterminated),
+                Some('"') => {
+                    self.next();
+                    return Ok(());
+                }
+                Some('\\') => {
+                    self.next();
+                    match self.next() {
+                        None => return Err(unterminated),
+                        Some('\n') => {}
+                        Some(char @ ('$' | '`' | '"' | '\\')) => word.push(char),
+                        Some(char) => {
+                            word.push('\\');
+                            word.push(char);
+                        }
+                    }
+                }
+                Some('`') => {
+                    return Err(ShellWordsError::UnsupportedSyntax {
+                        found: '`',
+                        line: self.line,
+                        column: self.column,
+                    })
+                }
+                Some('$') if self.vars.is_some() => self.parse_variable(word)?,
+                Some(char) => {
+                    self.next();
+                    word.push(char);
+                }
+            }
+        }
+    }
+
+    /// Parses `$VAR` or `${VAR}` and pushes the value to `word`.
+    fn parse_variable(&mut self, word: &mut String) -> Result<(), ShellWordsError> {
+        let (line, column) = (self.line, self.column);
+        self.next();
+        let braced = self.peek() == Some('{');
+        if braced {
+            self.next();
+        }
+        let unsupported = |parser: &Self, found| ShellWordsError::UnsupportedSyntax {
+            found,
+            line: parser.line,
+            column: parser.column,
+        };
+        match self.peek() {
+            // Positional and special parameters.
+            Some(found @ ('0'..='9' | '@' | '*' | '#' | '?' | '$' | '!' | '-')) => {
+                return Err(unsupported(self, found))
+            }
+            // Command substitution and arithmetic expansion.
+            Some(found @ '(') if !braced => return Err(unsupported(self, found)),
+            _ => {}
+        }
+        let name = self.take_while(|char| char.is_ascii_alphanumeric() || char == '_');
+        if braced {
+            match self.peek() {
+                None => return Err(ShellWordsError::UnterminatedVariable { line, column }),
+                Some('}') if !name.is_empty() => {
+                    self.next();
+                }
+                // E.g. `${}` or `${VAR:-default}`.
+                Some(found) => return Err(unsupported(self, found)),
+            }
+        }
+        if name.is_empty() {
+            // A lone `$` is kept as is.
+            word.push('$');
+            return Ok(());
+        }
+        match self.vars.and_then(|vars| vars.get(&name)) {
+            Some(value) => word.push_str(value),
+            None => {
+                return Err(ShellWordsError::UndefinedVariable { name, line, column });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod Command {
+        use crate::{Command, CommandExecutionError, ShellWordsError};
+        use std::ffi::OsString;
+
+        fn words(
+            cmd: Result<Command<(), CommandExecutionError>, ShellWordsError>,
+        ) -> Vec<OsString> {
+            let cmd = cmd.unwrap();
+            std::iter::once(cmd.program().to_owned())
+                .chain(cmd.arguments().iter().cloned())
+                .collect()
+        }
+
+        mod from_shell_words {
+            use super::words;
+            use crate::{Command, ReturnNothing, ShellWordsError};
+            use proptest::prelude::*;
+            use std::ffi::OsString;
+
+            fn split(input: &str) -> Vec<OsString> {
+                words(Command::from_shell_words(input, ReturnNothing))
+            }
+
+            fn error(input: &str) -> ShellWordsError {
+                Command::from_shell_words(input, ReturnNothing).unwrap_err()
+            }
+
+            #[test]
+            fn words_are_split_at_unquoted_blanks() {
+                assert_eq!(split("  a  b\tc\nd "), ["a", "b", "c", "d"]);
+                assert_eq!(split(r#"a' b 'c "d e" '' """#), ["a b c", "d e", "", ""]);
+            }
+
+            #[test]
+            fn other_whitespace_is_part_of_words() {
+                assert_eq!(split("a\r b\u{a0}c\u{3000}"), ["a\r", "b\u{a0}c\u{3000}"]);
+            }
+
+            #[test]
+            fn backslashes_escape_outside_of_single_quotes() {
+                assert_eq!(split(r"a\ b \'c\\"), ["a b", "'c\\"]);
+                assert_eq!(split("a\\\nb"), ["ab"]);
+                assert_eq!(split(r"'a\b'"), [r"a\b"]);
+                assert_eq!(split(r#"x "\a\"\\\$""#), ["x", r#"\a"\$"#]);
+            }
+
+            #[test]
+            fn comments_start_at_word_beginnings() {
+                assert_eq!(split("a#b # c d\ne"), ["a#b", "e"]);
+            }
+
+            #[test]
+            fn variables_are_not_expanded() {
+                assert_eq!(
+                    split(r#"echo $HOME "${HOME}" $1 ${A:-x}"#),
+                    ["echo", "$HOME", "${HOME}", "$1", "${A:-x}"]
+                );
+            }
+
+            #[test]
+            fn the_first_word_is_the_program() {
+                let cmd = Command::from_shell_words("ls -l 'my dir'", ReturnNothing).unwrap();
+                assert_eq!(cmd.program(), "ls");
+                assert_eq!(cmd.arguments(), &["-l", "my dir"]);
+            }
+
+            #[test]
+            fn errors_have_precise_positions() {
+                assert_eq!(
+                    error("echo 'a b"),
+                    ShellWordsError::UnterminatedQuote {
+                        quote: '\'',
+                        line: 1,
+                        column: 6
+                    }
+                );
+                assert_eq!(
+                    error("echo \\\n  a \"b\\\""),
+                    ShellWordsError::UnterminatedQuote {
+                        quote: '"',
+                        line: 2,
+                        column: 5
+                    }
+                );
+                assert_eq!(
+                    error("echo a\\"),
+                    ShellWordsError::TrailingBackslash { line: 1, column: 7 }
+                );
+                assert_eq!(
+                    error("ls | wc"),
+                    ShellWordsError::UnsupportedSyntax {
+                        found: '|',
+                        line: 1,
+                        column: 4
+                    }
+                );
+                assert_eq!(error(" # only a comment"), ShellWordsError::Empty);
+                assert_eq!(
+                    error("echo 'a b").to_string(),
+                    "Unterminated ' quote (line 1, column 6)"
+                );
+            }
+
+            proptest! {
+                #[test]
+                fn quoted_words_round_trip(input in proptest::collection::vec(any::<String>(), 1..5)) {
+                    let quoted = input
+                        .iter()
+                        .map(|word| format!("'{}'", word.replace('\'', r"'\''")))
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let input = input.into_iter().map(OsString::from).collect::<Vec<_>>();
+                    prop_assert_eq!(split(&quoted), input);
+                }
+            }
+        }
+
+        mod from_shell_words_with_vars {
+            use super::words;
+            use crate::{Command, ReturnNothing, ShellWordsError};
+            use std::{collections::HashMap, ffi::OsString};
+
+            fn vars() -> HashMap<String, String> {
+                let mut vars = HashMap::new();
+                vars.insert("SRC".to_owned(), "my dir".to_owned());
+                vars
+            }
+
+            fn split(input: &str) -> Vec<OsString> {
+                words(Command::from_shell_words_with_vars(
+                    input,
+                    &vars(),
+                    ReturnNothing,
+                ))
+            }
+
+            fn error(input: &str) -> ShellWordsError {
+                Command::from_shell_words_with_vars(input, &vars(), ReturnNothing).unwrap_err()
+            }
+
+            #[test]
+            fn unquoted_and_double_quoted_variables_are_expanded() {
+                assert_eq!(
+                    split(r#"a$SRC/ "${SRC}x" '$SRC' \$SRC $ "$""#),
+                    ["amy dir/", "my dirx", "$SRC", "$SRC", "$", "$"]
+                );
+            }
+
+            #[test]
+            fn unknown_variables_are_rejected() {
+                assert_eq!(
+                    error("echo \"$A\""),
+                    ShellWordsError::UndefinedVariable {
+                        name: "A".into(),
+                        line: 1,
+                        column: 7
+                    }
+                );
+            }
+
+            #[test]
+            fn unterminated_braces_are_rejected() {
+                assert_eq!(
+                    error("echo ${A"),
+                    ShellWordsError::UnterminatedVariable { line: 1, column: 6 }
+                );
+                assert_eq!(
+                    error("echo ${"),
+                    ShellWordsError::UnterminatedVariable { line: 1, column: 6 }
+                );
+            }
+
+            #[test]
+            fn unsupported_expansions_are_rejected_at_the_offending_character() {
+                let unsupported = |found, column| ShellWordsError::UnsupportedSyntax {
+                    found,
+                    line: 1,
+                    column,
+                };
+                assert_eq!(error("echo ${SRC:-x}"), unsupported(':', 11));
+                assert_eq!(error("echo ${}"), unsupported('}', 8));
+                assert_eq!(error("echo ${#SRC}"), unsupported('#', 8));
+                assert_eq!(error("echo $1abc"), unsupported('1', 7));
+                assert_eq!(error("echo \"${1}\""), unsupported('1', 9));
+                assert_eq!(error("echo \"$(date)\""), unsupported('(', 8));
+                assert_eq!(error("echo $((1+1))"), unsupported('(', 7));
+                for special in ['@', '*', '#', '?', '$', '!', '-'] {
+                    assert_eq!(
+                        error(&format!("echo ${}", special)),
+                        unsupported(special, 7)
+                    );
+                    assert_eq!(
+                        error(&format!("echo \"${}\"", special)),
+                        unsupported(special, 8)
+                    );
+                }
+            }
+        }
+    }
+}
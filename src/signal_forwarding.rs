@@ -0,0 +1,308 @@
+//! Forwarding of signals received by the current process to running sub-processes.
+//!
+//! Signal handlers are only installed while at least one registration for given
+//! signal exists, once the last registration is dropped the previous signal
+//! handler is restored.
+//!
+//! The signal handler just writes the signal number into a (self-)pipe, a
+//! dispatcher thread reads it from there and forwards it to all registered
+//! sub-processes.
+use std::{
+    io, mem,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread,
+};
+
+static STATE: Mutex<State> = Mutex::new(State {
+    dispatcher_started: false,
+    next_id: 0,
+    registrations: Vec::new(),
+    installed_handlers: Vec::new(),
+});
+
+/// The write end of the self-pipe or -1 if it wasn't created yet.
+static PIPE_WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+struct State {
+    dispatcher_started: bool,
+    next_id: u64,
+    registrations: Vec<Registration>,
+    /// Signals we installed a handler for and the handler they had before.
+    installed_handlers: Vec<(i32, libc::sigaction)>,
+}
+
+// SAFETY: `libc::sigaction` contains (on some targets) raw pointers but it's
+// just data describing a signal handler.
+unsafe impl Send for State {}
+
+struct Registration {
+    id: u64,
+    signals: Vec<i32>,
+    /// The pid (or negative process group id) signals are forwarded to.
+    target: Option<libc::pid_t>,
+    /// Signals received before the target was known.
+    pending: Vec<i32>,
+    /// Signals which were relayed to the target.
+    forwarded: Vec<i32>,
+}
+
+/// Forwards given signals to a sub-process as long as this guard isn't dropped.
+pub(crate) struct SignalForwarding {
+    id: u64,
+}
+
+impl SignalForwarding {
+    /// Starts catching given signals.
+    ///
+    /// Caught signals are queued until [`SignalForwarding::set_target()`] is called,
+    /// this allows registering the forwarding before the sub-process is spawned.
+    ///
+    /// Fails if a handler can not be installed for any of the signals, e.g. for
+    /// `SIGKILL`.
+    pub(crate) fn new(signals: &[i32]) -> Result<Self, io::Error> {
+        let mut state = lock_state();
+        start_dispatcher(&mut state)?;
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.registrations.push(Registration {
+            id,
+            signals: signals.to_owned(),
+            target: None,
+            pending: Vec::new(),
+            forwarded: Vec::new(),
+        });
+        // Must be created before installing handlers, so that on failure dropping
+        // it un-registers and restores the handlers which were already installed.
+        let forwarding = SignalForwarding { id };
+
+        let result = signals.iter().try_for_each(|&signal| {
+            if !state.installed_handlers.iter().any(|(s, _)| *s == signal) {
+                let old = install_handler(signal)?;
+                state.installed_handlers.push((signal, old));
+            }
+            Ok(())
+        });
+
+        // `forwarding` must not be dropped while we hold the lock.
+        drop(state);
+        result.map(|()| forwarding)
+    }
+
+    /// Sets the pid (or negative process group id) to which signals will be forwarded.
+    ///
+    /// Signals which were received before this was called are forwarded immediately.
+    pub(crate) fn set_target(&self, target: libc::pid_t) {
+        let mut state = lock_state();
+        if let Some(registration) = state.registrations.iter_mut().find(|r| r.id == self.id) {
+            registration.target = Some(target);
+            for signal in registration.pending.drain(..) {
+                send_signal(target, signal);
+                registration.forwarded.push(signal);
+            }
+        }
+    }
+
+    /// Returns the signals which were relayed to the target so far, in the order they were received.
+    pub(crate) fn forwarded_signals(&self) -> Vec<i32> {
+        let state = lock_state();
+        state
+            .registrations
+            .iter()
+            .find(|r| r.id == self.id)
+            .map(|r| r.forwarded.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Signals which never reached the sub-process (because no target was set, e.g. as
+/// spawning it failed) are raised again in the current process once its previous
+/// signal handler is restored, so that e.g. a `SIGINT` received while spawning isn't lost.
+impl Drop for SignalForwarding {
+    fn drop(&mut self) {
+        let mut guard = lock_state();
+        let state = &mut *guard;
+        // Only signals received before the target was set are pending.
+        let mut pending = match state.registrations.iter().position(|r| r.id == self.id) {
+            Some(index) => state.registrations.remove(index).pending,
+            None => Vec::new(),
+        };
+        let registrations = &state.registrations;
+        state.installed_handlers.retain(|(signal, old)| {
+            let still_used = registrations.iter().any(|r| r.signals.contains(signal));
+            if !still_used {
+                // SAFETY: `old` was returned by `sigaction` for this signal.
+                unsafe {
+                    libc::sigaction(*signal, old, std::ptr::null_mut());
+                }
+            }
+            still_used
+        });
+        // Signals still caught by us were already forwarded to the other sub-processes.
+        pending.retain(|signal| !state.installed_handlers.iter().any(|(s, _)| s == signal));
+        pending.dedup();
+
+        drop(guard);
+        for signal in pending {
+            // SAFETY: raise doesn't touch any memory.
+            unsafe {
+                libc::raise(signal);
+            }
+        }
+    }
+}
+
+fn lock_state() -> MutexGuard<'static, State> {
+    // The state is always left consistent, so poisoning can be ignored.
+    STATE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn start_dispatcher(state: &mut State) -> Result<(), io::Error> {
+    if state.dispatcher_started {
+        return Ok(());
+    }
+
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has space for two file descriptors.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let [read_fd, write_fd] = fds;
+    // SAFETY: both fds are valid and owned by us.
+    unsafe {
+        libc::fcntl(read_fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(write_fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        // The signal handler must never block.
+        libc::fcntl(write_fd, libc::F_SETFL, libc::O_NONBLOCK);
+    }
+
+    thread::Builder::new()
+        .name("mapped-command-signal-forwarding".to_owned())
+        .spawn(move || dispatch_signals(read_fd))?;
+
+    PIPE_WRITE_FD.store(write_fd, Ordering::SeqCst);
+    state.dispatcher_started = true;
+    Ok(())
+}
+
+fn dispatch_signals(read_fd: libc::c_int) {
+    let mut buffer = [0u8; 64];
+    loop {
+        // SAFETY: reads at most `buffer.len()` bytes into `buffer`.
+        let read = unsafe { libc::read(read_fd, buffer.as_mut_ptr() as *mut _, buffer.len()) };
+        if read <= 0 {
+            if read == -1 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return;
+        }
+
+        let mut state = lock_state();
+        for &signal in &buffer[..read as usize] {
+            let signal = i32::from(signal);
+            for registration in &mut state.registrations {
+                if registration.signals.contains(&signal) {
+                    match registration.target {
+                        Some(target) => {
+                            send_signal(target, signal);
+                            registration.forwarded.push(signal);
+                        }
+                        None => registration.pending.push(signal),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn send_signal(target: libc::pid_t, signal: i32) {
+    // SAFETY: kill doesn't touch any memory.
+    unsafe {
+        libc::kill(target, signal);
+    }
+}
+
+fn install_handler(signal: i32) -> Result<libc::sigaction, io::Error> {
+    // SAFETY: zeroed is a valid (empty) `sigaction`, we fully set it up before using it.
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut old: libc::sigaction = mem::zeroed();
+        if libc::sigaction(signal, &action, &mut old) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(old)
+    }
+}
+
+/// The signal handler, this must only do async-signal-safe operations.
+extern "C" fn handle_signal(signal: libc::c_int) {
+    let fd = PIPE_WRITE_FD.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // Signal numbers are small positive numbers, they fit into a byte.
+        let byte = signal as u8;
+        // SAFETY: `write` is async-signal-safe and writes one byte from `byte`.
+        unsafe {
+            libc::write(fd, &byte as *const u8 as *const _, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod SignalForwarding {
+        use super::super::{lock_state, SignalForwarding};
+        use std::{
+            mem,
+            sync::atomic::{AtomicBool, Ordering},
+            thread,
+            time::{Duration, Instant},
+        };
+
+        static RAISED: AtomicBool = AtomicBool::new(false);
+
+        extern "C" fn record_signal(_: libc::c_int) {
+            RAISED.store(true, Ordering::SeqCst);
+        }
+
+        #[cfg(target_os = "linux")]
+        #[test]
+        fn pending_signals_are_raised_again_if_no_target_was_set() {
+            // A real-time signal isn't used by anything else.
+            let signal = libc::SIGRTMIN() + 3;
+            unsafe {
+                let mut action: libc::sigaction = mem::zeroed();
+                action.sa_sigaction =
+                    record_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                libc::sigemptyset(&mut action.sa_mask);
+                assert_eq!(libc::sigaction(signal, &action, std::ptr::null_mut()), 0);
+            }
+
+            let forwarding = SignalForwarding::new(&[signal]).unwrap();
+            unsafe { libc::raise(signal) };
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !lock_state()
+                .registrations
+                .iter()
+                .any(|r| r.id == forwarding.id && !r.pending.is_empty())
+            {
+                assert!(Instant::now() < deadline, "signal was never dispatched");
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(!RAISED.load(Ordering::SeqCst));
+
+            drop(forwarding);
+            assert!(RAISED.load(Ordering::SeqCst));
+        }
+    }
+}
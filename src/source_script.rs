@@ -0,0 +1,249 @@
+//! Capturing the env changes done by sourcing a shell script.
+use crate::{Command, CommandExecutionError, EnvChange, MapStdout, UnexpectedExitStatus};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    io,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+};
+use thiserror::Error;
+
+/// Marks the end of the env captured before sourcing the script.
+///
+/// It's not a valid `KEY=VALUE` entry, so it can't be confused with a env variable.
+const SEPARATOR: &[u8] = b"__MAPPED_COMMAND_SOURCED__";
+
+/// Variables maintained by the shell itself, changes to them are ignored.
+///
+/// E.g. `bash` sets `_` to the last executed command and decrements `SHLVL` when it
+/// execs the last command.
+const SHELL_VARIABLES: &[&str] = &["_", "SHLVL", "PWD", "OLDPWD"];
+
+/// The shell code which sources the script (`$1`) and prints the env before and after.
+///
+/// The env is printed by `cat` reading its own (i.e. the exported) env from `/proc`,
+/// `env -0` would do the same but is a GNU extension. The output of the script is
+/// redirected to stderr so that it doesn't interfere with the captured env.
+const SOURCE_SCRIPT: &str = r#"script=$1; shift
+case $script in */*) ;; *) script=./$script ;; esac
+cat /proc/self/environ || exit
+printf '%s\0' __MAPPED_COMMAND_SOURCED__
+. "$script" >&2 || exit
+cat /proc/self/environ"#;
+
+/// Returns a command which sources given script and returns the env changes it did.
+///
+/// The script is sourced (i.e. `. script`) in given shell, the env exported before
+/// and after sourcing it is read from `/proc/self/environ` and the differences are
+/// returned as env updates, which can be applied to any other command using
+/// [`Command::with_env_updates()`]. Added and changed variables become a
+/// [`EnvChange::Set`], removed variables become a [`EnvChange::Remove`]. Variables
+/// maintained by the shell itself (`_`, `SHLVL`, `PWD` and `OLDPWD`) are ignored.
+///
+/// This is useful for toolchains which need to be set up by sourcing a script, like
+/// Emscripten's `emsdk_env.sh` or Yocto's `oe-init-build-env`:
+///
+/// ```no_run
+/// use mapped_command::{source_script_env, Command, ReturnNothing};
+///
+/// let env = source_script_env("bash", "/opt/emsdk/emsdk_env.sh").run()?;
+/// Command::new("emcc", ReturnNothing)
+///     .with_arguments(["main.c", "-o", "main.js"])
+///     .with_env_updates(&env)
+///     .run()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// Arguments added to the returned command (e.g. with [`Command::with_argument()`]) are
+/// passed as positional arguments to the script. The shell must support passing them
+/// (`bash` does), the env and working directory are set up as for any other command.
+/// The output the script writes to stdout is forwarded to stderr.
+///
+/// Fails if the shell fails, e.g. because sourcing the script failed.
+///
+/// *This is only available on linux.*
+pub fn source_script_env(
+    shell: impl Into<OsString>,
+    script: impl Into<OsString>,
+) -> Command<HashMap<OsString, EnvChange>, SourceScriptError> {
+    Command::new(shell, MapStdout(|stdout| parse_env_diff(&stdout))).with_arguments([
+        OsString::from("-c"),
+        SOURCE_SCRIPT.into(),
+        "sh".into(),
+        script.into(),
+    ])
+}
+
+/// Error returned by the command created by [`source_script_env()`].
+#[derive(Debug, Error)]
+pub enum SourceScriptError {
+    /// Spawning the shell failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// The shell exited with an unexpected exit status, e.g. because sourcing the script failed.
+    #[error(transparent)]
+    UnexpectedExitStatus(#[from] UnexpectedExitStatus),
+
+    /// The output of the shell is not the expected NUL separated env.
+    #[error("Capturing the env failed, unexpected output: {0}")]
+    InvalidOutput(String),
+}
+
+impl From<CommandExecutionError> for SourceScriptError {
+    fn from(err: CommandExecutionError) -> Self {
+        match err {
+            CommandExecutionError::Io(err) => SourceScriptError::Io(err),
+            CommandExecutionError::ProgramNotFound(err) => SourceScriptError::Io(err.into()),
+            CommandExecutionError::Validation(err) => SourceScriptError::Io(err.into()),
+            CommandExecutionError::UnexpectedExitStatus(err) => {
+                SourceScriptError::UnexpectedExitStatus(err)
+            }
+        }
+    }
+}
+
+/// Parses the env before and after sourcing the script and returns the differences.
+fn parse_env_diff(output: &[u8]) -> Result<HashMap<OsString, EnvChange>, SourceScriptError> {
+    let mut entries = output.split(|&byte| byte == 0);
+    let before = parse_env(entries.by_ref().take_while(|&entry| entry != SEPARATOR))?;
+    // The output ends with a `\0`, so the last entry is empty.
+    let after = parse_env(entries.filter(|entry| !entry.is_empty()))?;
+    if after.is_empty() {
+        return Err(SourceScriptError::InvalidOutput(
+            "missing the env after sourcing the script".into(),
+        ));
+    }
+
+    let mut updates = HashMap::new();
+    for (key, value) in &after {
+        if before.get(key) != Some(value) {
+            updates.insert(key.clone(), EnvChange::Set(value.clone()));
+        }
+    }
+    for key in before.keys() {
+        if !after.contains_key(key) {
+            updates.insert(key.clone(), EnvChange::Remove);
+        }
+    }
+    for key in SHELL_VARIABLES {
+        updates.remove(OsStr::new(key));
+    }
+    Ok(updates)
+}
+
+fn parse_env<'a>(
+    entries: impl Iterator<Item = &'a [u8]>,
+) -> Result<HashMap<OsString, OsString>, SourceScriptError> {
+    entries
+        .map(|entry| {
+            let idx = entry.iter().position(|&byte| byte == b'=').ok_or_else(|| {
+                SourceScriptError::InvalidOutput(format!(
+                    "env entry without '=': {:?}",
+                    OsStr::from_bytes(entry)
+                ))
+            })?;
+            Ok((
+                OsString::from_vec(entry[..idx].to_vec()),
+                OsString::from_vec(entry[idx + 1..].to_vec()),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod source_script_env {
+        use crate::{source_script_env, EnvChange, ExecResult, SourceScriptError};
+        use std::{collections::HashMap, ffi::OsString, fs};
+
+        #[test]
+        fn diffs_the_env_before_and_after_sourcing() {
+            let output = b"A=1\0B=2\0C=x\ny\0_=/bin/sh\0SHLVL=1\0__MAPPED_COMMAND_SOURCED__\0A=1\0B=3\0D=4\0_=/bin/env\0SHLVL=0\0";
+            let env = source_script_env("sh", "script.sh")
+                .with_exec_replacement_callback(move |_, _| {
+                    Ok(ExecResult {
+                        exit_status: 0.into(),
+                        stdout: Some(output.to_vec()),
+                        stderr: None,
+                        ..Default::default()
+                    })
+                })
+                .run()
+                .unwrap();
+
+            let mut expected = HashMap::new();
+            expected.insert(OsString::from("B"), EnvChange::Set("3".into()));
+            expected.insert(OsString::from("C"), EnvChange::Remove);
+            expected.insert(OsString::from("D"), EnvChange::Set("4".into()));
+            assert_eq!(env, expected);
+        }
+
+        #[test]
+        fn invalid_output_is_rejected() {
+            let err = source_script_env("sh", "script.sh")
+                .with_exec_replacement_callback(|_, _| {
+                    Ok(ExecResult {
+                        exit_status: 0.into(),
+                        stdout: Some(b"no env".to_vec()),
+                        stderr: None,
+                        ..Default::default()
+                    })
+                })
+                .run()
+                .unwrap_err();
+
+            assert!(
+                matches!(err, SourceScriptError::InvalidOutput(_)),
+                "{:?}",
+                err
+            );
+        }
+
+        #[test]
+        fn captures_the_env_changes_of_a_real_script() {
+            let script = std::env::temp_dir()
+                .join(format!("mapped-command-{}-setup.sh", std::process::id()));
+            fs::write(
+                &script,
+                "echo 'setting up'\nexport TOOL_HOME=\"/opt/$1\"\nexport PATH=\"$TOOL_HOME/bin:$PATH\"\nunset TO_REMOVE\n",
+            )
+            .unwrap();
+
+            let result = source_script_env("bash", &script)
+                .with_argument("tool")
+                .with_env_update("TO_REMOVE", "x")
+                .run();
+            fs::remove_file(&script).unwrap();
+            let env = result.unwrap();
+
+            let path = std::env::var("PATH").unwrap();
+            let mut expected = HashMap::new();
+            expected.insert(
+                OsString::from("TOOL_HOME"),
+                EnvChange::Set("/opt/tool".into()),
+            );
+            expected.insert(
+                OsString::from("PATH"),
+                EnvChange::Set(format!("/opt/tool/bin:{}", path).into()),
+            );
+            expected.insert(OsString::from("TO_REMOVE"), EnvChange::Remove);
+            assert_eq!(env, expected);
+        }
+
+        #[test]
+        fn failing_scripts_fail_the_command() {
+            let err = source_script_env("sh", "/this/does/not/exist.sh")
+                .run()
+                .unwrap_err();
+
+            assert!(
+                matches!(err, SourceScriptError::UnexpectedExitStatus(_)),
+                "{:?}",
+                err
+            );
+        }
+    }
+}
@@ -1,7 +1,25 @@
 use crate::{
-    Command, ExecResult, ExitStatus, OpaqueOsExitStatus, OutputMapping, UnexpectedExitStatus,
+    join_path_lists, Command, EnvChange, ExecResult, ExitStatus, OpaqueOsExitStatus, OutputMapping,
+    UnexpectedExitStatus,
+};
+#[cfg(target_os = "linux")]
+use crate::{pidfd, pty};
+#[cfg(unix)]
+use crate::{signal_forwarding::SignalForwarding, Resource, ResourceLimit, SwitchCredentialsError};
+#[cfg(unix)]
+use std::{
+    convert::TryFrom,
+    fs::File,
+    os::unix::{
+        io::{AsRawFd, FromRawFd, OwnedFd},
+        process::CommandExt,
+    },
+};
+use std::{
+    io::{self, Read},
+    process, thread,
+    time::{Duration, Instant},
 };
-use std::{io, process};
 
 /// This method is a `exec_replacement_callback` but it actually executes the process.
 pub(super) fn actual_exec_exec_replacement_callback<O, E>(
@@ -11,42 +29,41 @@ pub(super) fn actual_exec_exec_replacement_callback<O, E>(
 where
     E: From<io::Error> + From<UnexpectedExitStatus>,
 {
-    let mut sys_cmd = process::Command::new(cmd.program());
-    sys_cmd.args(cmd.arguments());
-
-    // This might not be the fasted thing, but it is the most consistent thing
-    // because now we always will have the environment variables returned by
-    // `.create_expected_env_iter()` *which we can  properly test to work correctly*.
-    sys_cmd.env_clear();
-    sys_cmd.envs(cmd.create_expected_env_iter());
-
-    if let Some(wd_override) = cmd.working_directory_override() {
-        sys_cmd.current_dir(wd_override);
-    }
-
     let capture_stdout = return_settings.capture_stdout();
     let capture_stderr = return_settings.capture_stderr();
 
-    if capture_stdout {
-        sys_cmd.stdout(process::Stdio::piped());
-    }
+    let Spawned {
+        mut child,
+        #[cfg(target_os = "linux")]
+        pty_master,
+    } = spawn(&cmd, capture_stdout, capture_stderr)?;
 
-    if capture_stderr {
-        sys_cmd.stderr(process::Stdio::piped());
-    }
-
-    let child = sys_cmd.spawn()?;
+    // The output must be read even if it's not captured, else the sub-process might block.
+    #[cfg(target_os = "linux")]
+    let pty_output = pty_master.map(pty::spawn_read_output);
 
-    // `wait_with_output` will only parse stdout/stderr if it was setup with Stdio::piped()
-    // As we only setup `Stdio::piped()` if we need capturing this only captures when we want
-    // it to capture. (Non captured stdout/stderr will produce an empty vector).
+    // We only setup `Stdio::piped()` if we need capturing, so non captured stdout/stderr
+    // will produce an empty vector.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
     let process::Output {
-        stdout,
+        mut stdout,
         stderr,
         status: exit_status,
     } = child.wait_with_output()?;
 
+    #[cfg(target_os = "linux")]
+    if let Some(pty_output) = pty_output {
+        let output = join_read_to_end(Some(pty_output))?;
+        if capture_stdout {
+            stdout = output;
+        }
+    }
+
     let exit_status = map_std_exit_status(exit_status);
+    #[cfg(unix)]
+    let forwarded_signals = child.forwarded_signals().to_owned();
+    #[cfg(not(unix))]
+    let forwarded_signals = Vec::new();
 
     let stdout = if capture_stdout {
         Some(stdout)
@@ -66,10 +83,822 @@ where
         exit_status,
         stdout,
         stderr,
+        forwarded_signals,
+    })
+}
+
+/// A spawned sub-process and everything which must live as long as it runs.
+pub(crate) struct Spawned {
+    pub(crate) child: ChildGuard,
+    /// The master side of the pseudo-terminal, if [`Command::pty()`] is set.
+    #[cfg(target_os = "linux")]
+    pub(crate) pty_master: Option<File>,
+}
+
+/// Spawns the sub-process with all settings from given [`Command`].
+pub(crate) fn spawn<O, E>(
+    cmd: &Command<O, E>,
+    capture_stdout: bool,
+    capture_stderr: bool,
+) -> Result<Spawned, io::Error>
+where
+    E: From<io::Error> + From<UnexpectedExitStatus>,
+{
+    if cmd.validate_before_spawn() {
+        cmd.validate()?;
+    }
+
+    let PreparedCommand {
+        command: mut sys_cmd,
+        #[cfg(unix)]
+        credentials_failure,
+    } = create_sys_command(cmd, capture_stdout, capture_stderr)?;
+
+    #[cfg(unix)]
+    let owns_process_group = cmd.owns_process_group();
+    #[cfg(not(unix))]
+    let owns_process_group = false;
+
+    #[cfg(unix)]
+    let signal_forwarding = if cmd.forwarded_signals().is_empty() {
+        None
+    } else {
+        Some(SignalForwarding::new(cmd.forwarded_signals())?)
+    };
+
+    #[cfg(target_os = "linux")]
+    let pty_master = match cmd.pty() {
+        Some(size) => {
+            let (master, slave) = pty::open(size)?;
+            sys_cmd.stdin(slave.try_clone()?);
+            sys_cmd.stdout(slave.try_clone()?);
+            sys_cmd.stderr(slave);
+            Some(master)
+        }
+        None => None,
+    };
+
+    let child = sys_cmd.spawn();
+    // Closes our copies of the pseudo-terminal slave (if any), without this
+    // reading from the master would never end.
+    drop(sys_cmd);
+    #[cfg(unix)]
+    let child = child.map_err(|err| {
+        if credentials_failure
+            .as_ref()
+            .is_some_and(switching_credentials_failed)
+        {
+            io::Error::new(err.kind(), SwitchCredentialsError::new(cmd, err))
+        } else {
+            err
+        }
+    });
+    let child = child.map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => cmd.resolve_program().err().map_or(err, Into::into),
+        _ => err,
+    });
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut child = ChildGuard::new(child?, owns_process_group);
+
+    #[cfg(unix)]
+    if let Some(signal_forwarding) = signal_forwarding {
+        let pid = child.id() as libc::pid_t;
+        signal_forwarding.set_target(if owns_process_group { -pid } else { pid });
+        child.signal_forwarding = Some(signal_forwarding);
+    }
+
+    Ok(Spawned {
+        child,
+        #[cfg(target_os = "linux")]
+        pty_master,
+    })
+}
+
+/// A `std::process::Command` and what is needed to interpret its spawn errors.
+struct PreparedCommand {
+    command: process::Command,
+    /// Receives a byte if switching the credentials failed in the sub-process.
+    #[cfg(unix)]
+    credentials_failure: Option<File>,
+}
+
+/// Creates a `std::process::Command` with all settings from given [`Command`].
+fn create_sys_command<O, E>(
+    cmd: &Command<O, E>,
+    capture_stdout: bool,
+    capture_stderr: bool,
+) -> Result<PreparedCommand, io::Error>
+where
+    E: From<io::Error> + From<UnexpectedExitStatus>,
+{
+    let mut sys_cmd = process::Command::new(cmd.program());
+    sys_cmd.args(cmd.arguments());
+
+    // If the env is inherited, only the updates are applied on top of it. Std only uses
+    // `posix_spawn` if no `pre_exec` callbacks are registered and `PATH` isn't changed
+    // for a program looked up in it, so rebuilding the whole env (including `PATH`)
+    // would make it fall back to fork and exec, which is a lot slower.
+    if cmd.inherit_env() && cmd.env_inherit_filter().is_none() {
+        for (key, change) in cmd.env_updates() {
+            match change {
+                EnvChange::Set(value) => {
+                    sys_cmd.env(key, value);
+                }
+                EnvChange::Remove => {
+                    sys_cmd.env_remove(key);
+                }
+                EnvChange::Inherit => {}
+                EnvChange::Prepend(paths) => {
+                    let inherited = cmd.inherited_env_var(key);
+                    sys_cmd.env(key, join_path_lists(Some(paths), inherited.as_deref()));
+                }
+                EnvChange::Append(paths) => {
+                    let inherited = cmd.inherited_env_var(key);
+                    sys_cmd.env(key, join_path_lists(inherited.as_deref(), Some(paths)));
+                }
+            }
+        }
+    } else {
+        // This might not be the fasted thing, but it is the most consistent thing
+        // because now we always will have the environment variables returned by
+        // `.create_expected_env_iter()` *which we can  properly test to work correctly*.
+        sys_cmd.env_clear();
+        sys_cmd.envs(cmd.create_expected_env_iter());
+    }
+
+    if let Some(wd_override) = cmd.working_directory_override() {
+        sys_cmd.current_dir(wd_override);
+    }
+
+    if capture_stdout {
+        sys_cmd.stdout(process::Stdio::piped());
+    }
+
+    if capture_stderr {
+        sys_cmd.stderr(process::Stdio::piped());
+    }
+
+    Ok(PreparedCommand {
+        #[cfg(unix)]
+        credentials_failure: apply_unix_settings(cmd, &mut sys_cmd)?,
+        command: sys_cmd,
+    })
+}
+
+/// Applies the unix specific settings.
+///
+/// If credentials are switched the read end of a pipe is returned, which receives
+/// a byte if switching them failed.
+#[cfg(unix)]
+fn apply_unix_settings<O, E>(
+    cmd: &Command<O, E>,
+    sys_cmd: &mut process::Command,
+) -> Result<Option<File>, io::Error>
+where
+    E: From<io::Error> + From<UnexpectedExitStatus>,
+{
+    if cmd.creates_session() {
+        #[cfg(target_os = "linux")]
+        let pty = cmd.pty().is_some();
+        #[cfg(not(target_os = "linux"))]
+        let pty = false;
+        // `setsid` fails if the process already is a process group leader,
+        // but it implicitly creates a new process group anyway.
+        unsafe {
+            sys_cmd.pre_exec(move || {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // Stdin already is the pseudo-terminal, make it our controlling terminal.
+                if pty && libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    } else if cmd.new_process_group() {
+        sys_cmd.process_group(0);
+    }
+
+    // Resource limits must be applied before the credentials are switched, as
+    // raising hard limits requires privileges the new credentials might not have.
+    if !cmd.resource_limits().is_empty() {
+        let limits = cmd
+            .resource_limits()
+            .iter()
+            .map(|limit| {
+                let rlimit = libc::rlimit {
+                    rlim_cur: raw_limit_value(limit.resource, limit.soft)?,
+                    rlim_max: raw_limit_value(limit.resource, limit.hard)?,
+                };
+                Ok((raw_resource(limit.resource), rlimit))
+            })
+            .collect::<Result<Vec<_>, io::Error>>()?;
+        unsafe {
+            sys_cmd.pre_exec(move || {
+                for (resource, rlimit) in &limits {
+                    if libc::setrlimit(*resource, rlimit) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // Lowering the niceness also requires privileges.
+    if let Some(niceness) = cmd.niceness() {
+        unsafe {
+            sys_cmd.pre_exec(move || {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, niceness) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    let mut credentials_failure = None;
+    if cmd.switches_credentials() {
+        // `CommandExt::groups` is not stable and `pre_exec` callbacks run after
+        // `CommandExt::uid` was applied, at which point we might no longer have the
+        // privileges to change the groups, resource limits or niceness. So we switch all
+        // credentials in a `pre_exec` callback registered after the ones needing them.
+        // This also allows telling the parent that the error comes from this step.
+        let (uid, gid) = (cmd.uid(), cmd.gid());
+        // Allocating after forking isn't safe, so it must be done here.
+        let groups = cmd
+            .supplementary_groups()
+            .map(|groups| groups.iter().map(|&g| g as libc::gid_t).collect::<Vec<_>>());
+        let (failure_reader, failure_writer) = cloexec_pipe()?;
+        unsafe {
+            sys_cmd.pre_exec(move || {
+                let result = switch_credentials(uid, gid, groups.as_deref());
+                if result.is_err() {
+                    libc::write(failure_writer.as_raw_fd(), [1u8].as_ptr().cast(), 1);
+                }
+                result
+            });
+        }
+        credentials_failure = Some(failure_reader);
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(signal) = cmd.parent_death_signal() {
+        let parent = unsafe { libc::getpid() };
+        unsafe {
+            sys_cmd.pre_exec(move || {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, signal as libc::c_ulong) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // The parent might have died before `prctl` was called, in which
+                // case we got re-parented and will never receive the signal.
+                if libc::getppid() != parent {
+                    libc::raise(signal);
+                    return Err(io::Error::from_raw_os_error(libc::ESRCH));
+                }
+                Ok(())
+            });
+        }
+    }
+
+    if let Some(arg0) = cmd.arg0() {
+        sys_cmd.arg0(arg0);
+    }
+
+    if let Some(umask) = cmd.umask() {
+        unsafe {
+            sys_cmd.pre_exec(move || {
+                libc::umask(umask as libc::mode_t);
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(cpus) = cmd.cpu_affinity() {
+        // SAFETY: an all zero `cpu_set_t` is an empty set.
+        let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        let max_cpus = std::mem::size_of::<libc::cpu_set_t>() * 8;
+        let valid = cpus.iter().all(|&cpu| cpu < max_cpus);
+        if valid {
+            for &cpu in cpus {
+                // SAFETY: `cpu` is in bounds of the set.
+                unsafe { libc::CPU_SET(cpu, &mut cpu_set) };
+            }
+        }
+        unsafe {
+            sys_cmd.pre_exec(move || {
+                if !valid {
+                    return Err(io::Error::from_raw_os_error(libc::EINVAL));
+                }
+                if libc::sched_setaffinity(0, std::mem::size_of_val(&cpu_set), &cpu_set) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    if !cmd.inherited_fds().is_empty() {
+        let fds = cmd
+            .inherited_fds()
+            .iter()
+            .map(|fd| (fd.raw_fd(), fd.target_fd()))
+            .collect::<Vec<_>>();
+        // Sources might collide with targets, so all sources are first moved
+        // above the highest target before they are moved to their targets.
+        let min_temp_fd = fds.iter().map(|&(_, target)| target).max().unwrap_or(0) + 1;
+        let mut temp_fds = vec![-1; fds.len()];
+        unsafe {
+            sys_cmd.pre_exec(move || {
+                for (&(source, _), temp) in fds.iter().zip(temp_fds.iter_mut()) {
+                    *temp = libc::fcntl(source, libc::F_DUPFD_CLOEXEC, min_temp_fd);
+                    if *temp == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                // `dup2` clears the close-on-exec flag of the new fd.
+                for (&(_, target), &temp) in fds.iter().zip(temp_fds.iter()) {
+                    if libc::dup2(temp, target) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    for hook in cmd.pre_exec_hooks() {
+        let hook = hook.hook();
+        // SAFETY: upheld by the creator of the `PreExecHook`.
+        unsafe {
+            sys_cmd.pre_exec(move || hook());
+        }
+    }
+
+    Ok(credentials_failure)
+}
+
+/// Switches the credentials of the current process, used after forking.
+///
+/// This must only do async-signal-safe operations.
+#[cfg(unix)]
+fn switch_credentials(
+    uid: Option<u32>,
+    gid: Option<u32>,
+    groups: Option<&[libc::gid_t]>,
+) -> Result<(), io::Error> {
+    // SAFETY: the functions only read the groups slice.
+    unsafe {
+        match groups {
+            Some(groups) => {
+                if libc::setgroups(groups.len() as _, groups.as_ptr()) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            // Like `CommandExt::uid`, drop the supplementary groups of root.
+            None => {
+                if uid.is_some()
+                    && libc::getuid() == 0
+                    && libc::setgroups(0, std::ptr::null()) == -1
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        if let Some(gid) = gid {
+            if libc::setgid(gid as libc::gid_t) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(uid) = uid {
+            if libc::setuid(uid as libc::uid_t) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Creates a pipe whose ends are closed on exec.
+#[cfg(unix)]
+fn cloexec_pipe() -> Result<(File, OwnedFd), io::Error> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has space for two file descriptors.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both fds are valid and owned by nobody else.
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    for fd in fds {
+        // SAFETY: fcntl with these commands doesn't touch any memory.
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok((reader, writer))
+}
+
+/// Returns true if the credential switching `pre_exec` callback reported a failure.
+#[cfg(unix)]
+fn switching_credentials_failed(failure_reader: &File) -> bool {
+    // The byte is written before the sub-process exits, so it never needs to be waited for.
+    set_nonblocking(failure_reader).is_ok() && matches!((&*failure_reader).read(&mut [0]), Ok(1))
+}
+
+// The type of the resource constants differs between targets.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RawResource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type RawResource = libc::c_int;
+
+/// Converts a limit value to a `rlim_t`, which is only 32 bits on some targets.
+#[cfg(unix)]
+fn raw_limit_value(resource: Resource, value: u64) -> Result<libc::rlim_t, io::Error> {
+    if value == ResourceLimit::UNLIMITED {
+        return Ok(libc::RLIM_INFINITY);
+    }
+    #[allow(clippy::useless_conversion, clippy::unnecessary_fallible_conversions)]
+    libc::rlim_t::try_from(value).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Resource limit {} for {:?} is too large for this target",
+                value, resource
+            ),
+        )
+    })
+}
+
+#[cfg(unix)]
+fn raw_resource(resource: Resource) -> RawResource {
+    match resource {
+        Resource::Cpu => libc::RLIMIT_CPU,
+        Resource::FileSize => libc::RLIMIT_FSIZE,
+        Resource::Data => libc::RLIMIT_DATA,
+        Resource::Stack => libc::RLIMIT_STACK,
+        Resource::CoreSize => libc::RLIMIT_CORE,
+        Resource::OpenFiles => libc::RLIMIT_NOFILE,
+        Resource::AddressSpace => libc::RLIMIT_AS,
+        Resource::Processes => libc::RLIMIT_NPROC,
+    }
+}
+
+/// Wrapper around a spawned `std::process::Child` which makes sure it doesn't outlive its owner.
+///
+/// If the guard is dropped before the child exited the child is killed and waited on.
+/// If the child is the leader of its own process group the whole process group is killed,
+/// this also happens once the child exited normally.
+///
+/// On unix the child is only reaped after the process group was killed and the signal
+/// forwarding was stopped, so neither can reach a process which recycled its PID. On
+/// linux the child is additionally tracked through a pidfd (if supported by the kernel).
+pub(crate) struct ChildGuard {
+    child: process::Child,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    owns_process_group: bool,
+    #[cfg(target_os = "linux")]
+    pidfd: Option<OwnedFd>,
+    #[cfg(unix)]
+    pub(crate) signal_forwarding: Option<SignalForwarding>,
+    /// The signals forwarded to the child, known once it exited.
+    #[cfg(unix)]
+    forwarded_signals: Vec<i32>,
+    exited: bool,
+}
+
+impl ChildGuard {
+    pub(crate) fn new(child: process::Child, owns_process_group: bool) -> Self {
+        // Opening the pidfd is race free as the child can't be reaped before we wait on it.
+        #[cfg(target_os = "linux")]
+        let pidfd = pidfd::open(child.id() as libc::pid_t).ok().flatten();
+        ChildGuard {
+            child,
+            owns_process_group,
+            #[cfg(target_os = "linux")]
+            pidfd,
+            #[cfg(unix)]
+            signal_forwarding: None,
+            #[cfg(unix)]
+            forwarded_signals: Vec::new(),
+            exited: false,
+        }
+    }
+
+    /// Returns the PID of the child.
+    pub(crate) fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Waits for the child to exit.
+    pub(crate) fn wait(&mut self) -> Result<process::ExitStatus, io::Error> {
+        self.wait_timeout(None)
+            .map(|status| status.expect("waiting without timeout always returns a status"))
+    }
+
+    /// Waits for the child to exit, returns `None` if it didn't exit within given timeout.
+    ///
+    /// This doesn't busy loop, on linux with pidfd support it doesn't poll at all.
+    pub(crate) fn wait_timeout(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<process::ExitStatus>, io::Error> {
+        if !self.exited {
+            if !self.wait_exited(timeout)? {
+                return Ok(None);
+            }
+            #[cfg(unix)]
+            if let Some(signal_forwarding) = self.signal_forwarding.take() {
+                self.forwarded_signals = signal_forwarding.forwarded_signals();
+            }
+            self.kill_process_group();
+        }
+        let status = self.child.wait()?;
+        self.exited = true;
+        Ok(Some(status))
+    }
+
+    /// Returns the signals which were forwarded to the child, empty until it exited.
+    #[cfg(unix)]
+    pub(crate) fn forwarded_signals(&self) -> &[i32] {
+        &self.forwarded_signals
+    }
+
+    /// Sends given signal to the child.
+    ///
+    /// Fails with `InvalidInput` if the child was already reaped.
+    #[cfg(unix)]
+    pub(crate) fn send_signal(&self, signal: i32) -> Result<(), io::Error> {
+        if self.exited {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "process was already reaped",
+            ));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(pidfd) = &self.pidfd {
+            return pidfd::send_signal(pidfd, signal);
+        }
+        // SAFETY: kill doesn't touch any memory, the child is not reaped so its PID can't be recycled.
+        if unsafe { libc::kill(self.child.id() as libc::pid_t, signal) } == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Waits until the child exited without reaping it.
+    #[cfg(unix)]
+    fn wait_exited(&mut self, timeout: Option<Duration>) -> Result<bool, io::Error> {
+        #[cfg(target_os = "linux")]
+        if let Some(pidfd) = &self.pidfd {
+            return pidfd::wait_exited(pidfd, timeout);
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut poll_interval = Duration::from_millis(1);
+        loop {
+            let mut flags = libc::WEXITED | libc::WNOWAIT;
+            if deadline.is_some() {
+                flags |= libc::WNOHANG;
+            }
+            // SAFETY: zeroed is a valid `siginfo_t`, it's only written to.
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            let result = unsafe {
+                libc::waitid(libc::P_PID, self.child.id() as libc::id_t, &mut info, flags)
+            };
+            if result == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            // With `WNOHANG` the pid is 0 if the child didn't exit yet.
+            if unsafe { info.si_pid() } != 0 {
+                return Ok(true);
+            }
+            let deadline = deadline.expect("only WNOHANG returns early");
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+            thread::sleep(poll_interval.min(deadline - now));
+            poll_interval = (poll_interval * 2).min(Duration::from_millis(50));
+        }
+    }
+
+    /// Waits until the child exited, on non unix targets this does reap it.
+    #[cfg(not(unix))]
+    fn wait_exited(&mut self, timeout: Option<Duration>) -> Result<bool, io::Error> {
+        let deadline = match timeout {
+            Some(timeout) => Instant::now() + timeout,
+            None => return self.child.wait().map(|_| true),
+        };
+        let mut poll_interval = Duration::from_millis(1);
+        loop {
+            if self.child.try_wait()?.is_some() {
+                return Ok(true);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+            thread::sleep(poll_interval.min(deadline - now));
+            poll_interval = (poll_interval * 2).min(Duration::from_millis(50));
+        }
+    }
+
+    /// Like `Child::wait_with_output` but respecting the process group handling.
+    ///
+    /// Other processes in the process group might have inherited the stdout/stderr pipes,
+    /// so we can't read them to the end before the child exited. On unix the pipes are
+    /// read in the current thread using `poll`, if we own the process group the pidfd is
+    /// polled too, so that the process group can be killed once the child exited. Else
+    /// (or if pidfds are not supported) we read them in separate threads and join them
+    /// after the child exited (and the process group was killed).
+    pub(crate) fn wait_with_output(&mut self) -> Result<process::Output, io::Error> {
+        #[cfg(target_os = "linux")]
+        let can_poll = !self.owns_process_group || self.pidfd.is_some();
+        #[cfg(all(unix, not(target_os = "linux")))]
+        let can_poll = !self.owns_process_group;
+        #[cfg(unix)]
+        if can_poll {
+            return self.poll_output();
+        }
+
+        let stdout = self.child.stdout.take().map(spawn_read_to_end);
+        let stderr = self.child.stderr.take().map(spawn_read_to_end);
+
+        let status = self.wait()?;
+
+        Ok(process::Output {
+            status,
+            stdout: join_read_to_end(stdout)?,
+            stderr: join_read_to_end(stderr)?,
+        })
+    }
+
+    /// Reads stdout/stderr until both are closed, waiting for the child in between if
+    /// we own its process group. See [`ChildGuard::wait_with_output()`].
+    #[cfg(unix)]
+    fn poll_output(&mut self) -> Result<process::Output, io::Error> {
+        let mut pipes = [
+            self.child
+                .stdout
+                .take()
+                .map(|pipe| File::from(OwnedFd::from(pipe))),
+            self.child
+                .stderr
+                .take()
+                .map(|pipe| File::from(OwnedFd::from(pipe))),
+        ];
+        for pipe in pipes.iter().flatten() {
+            set_nonblocking(pipe)?;
+        }
+        let mut outputs = [Vec::new(), Vec::new()];
+        #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+        let mut status = None;
+        loop {
+            let mut poll_fds = Vec::with_capacity(3);
+            for pipe in pipes.iter().flatten() {
+                poll_fds.push(poll_fd(pipe.as_raw_fd()));
+            }
+            #[cfg(target_os = "linux")]
+            let poll_pidfd = self.owns_process_group && status.is_none();
+            #[cfg(target_os = "linux")]
+            if poll_pidfd {
+                let pidfd = self.pidfd.as_ref().expect("only polled with a pidfd");
+                poll_fds.push(poll_fd(pidfd.as_raw_fd()));
+            }
+            if poll_fds.is_empty() {
+                break;
+            }
+
+            // SAFETY: `poll_fds` is a valid array of `pollfd`s with given length.
+            if unsafe { libc::poll(poll_fds.as_mut_ptr(), poll_fds.len() as _, -1) } == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+
+            let mut poll_fds = poll_fds.iter();
+            for (pipe, output) in pipes.iter_mut().zip(outputs.iter_mut()) {
+                let source = match pipe {
+                    Some(source) => source,
+                    None => continue,
+                };
+                let ready = poll_fds.next().expect("one pollfd per pipe").revents != 0;
+                if ready && read_available(source, output)? {
+                    *pipe = None;
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if poll_pidfd && poll_fds.next().expect("pollfd of the pidfd").revents != 0 {
+                status = Some(self.wait()?);
+            }
+        }
+
+        let status = match status {
+            Some(status) => status,
+            None => self.wait()?,
+        };
+        let [stdout, stderr] = outputs;
+        Ok(process::Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    fn kill_process_group(&self) {
+        #[cfg(unix)]
+        if self.owns_process_group {
+            kill_process_group(self.child.id() as libc::pid_t);
+        }
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if !self.exited {
+            #[cfg(unix)]
+            let _ = self.send_signal(libc::SIGKILL);
+            #[cfg(not(unix))]
+            let _ = self.child.kill();
+            let _ = self.wait();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn poll_fd(fd: libc::c_int) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }
+}
+
+#[cfg(unix)]
+fn set_nonblocking(fd: &File) -> Result<(), io::Error> {
+    // SAFETY: fcntl with these commands doesn't touch any memory.
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+    if flags == -1
+        || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads all currently available data from a non-blocking source, returns true on EOF.
+#[cfg(unix)]
+fn read_available(mut source: &File, output: &mut Vec<u8>) -> Result<bool, io::Error> {
+    // `read_to_end` keeps the data read before an error.
+    match source.read_to_end(output) {
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+fn spawn_read_to_end(
+    mut source: impl Read + Send + 'static,
+) -> thread::JoinHandle<Result<Vec<u8>, io::Error>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        source.read_to_end(&mut buffer)?;
+        Ok(buffer)
     })
 }
 
-fn map_std_exit_status(exit_status: std::process::ExitStatus) -> ExitStatus {
+fn join_read_to_end(
+    handle: Option<thread::JoinHandle<Result<Vec<u8>, io::Error>>>,
+) -> Result<Vec<u8>, io::Error> {
+    match handle {
+        Some(handle) => handle
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Sends `SIGKILL` to all processes in given process group.
+///
+/// Failing because the process group no longer exists is fine.
+#[cfg(unix)]
+pub(crate) fn kill_process_group(process_group: libc::pid_t) {
+    // SAFETY: kill doesn't touch any memory, a negative pid addresses the process group.
+    unsafe {
+        libc::kill(-process_group, libc::SIGKILL);
+    }
+}
+
+pub(crate) fn map_std_exit_status(exit_status: std::process::ExitStatus) -> ExitStatus {
     if let Some(code) = exit_status.code() {
         let code = cast_exit_code(code);
         ExitStatus::Code(code)
@@ -91,8 +920,7 @@ fn map_std_exit_status(exit_status: std::process::ExitStatus) -> ExitStatus {
             // unexpected unix like system you can run into this if the subprocess was
             // terminated in a unlikely fashion.
             let signal = exit_status.signal().unwrap_or(0x7F);
-            let signal = signal;
-            return OpaqueOsExitStatus::from_signal_number(signal).into();
+            OpaqueOsExitStatus::from_signal_number(signal).into()
         }
         #[cfg(not(unix))]
         unreachable!("run on unsupported target family, please open issue on github");
@@ -129,7 +957,7 @@ mod tests {
             .run()
             .unwrap();
 
-        assert_eq!(String::from_utf8_lossy(&*cap), "hy there\n");
+        assert_eq!(String::from_utf8_lossy(&cap), "hy there\n");
     }
 
     #[cfg(target_os = "linux")]
@@ -148,7 +976,7 @@ mod tests {
     #[test]
     fn with_env() {
         let out = Command::new("bash", ReturnStdout)
-            .with_arguments(&["-c", "echo $MAPPED_COMMAND_ENV_TEST"])
+            .with_arguments(["-c", "echo $MAPPED_COMMAND_ENV_TEST"])
             .with_inherit_env(false)
             .with_env_update("MAPPED_COMMAND_ENV_TEST", "yoyo")
             .run()
@@ -168,6 +996,630 @@ mod tests {
         assert_eq!(String::from_utf8_lossy(&out), "/\n");
     }
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn with_new_process_group_kills_left_over_processes() {
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments(["-c", "sleep 600 & echo $!"])
+            .with_new_process_group(true)
+            .run()
+            .unwrap();
+
+        assert_process_terminates(out.trim().parse().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn large_stdout_and_stderr_outputs_are_captured() {
+        for new_process_group in [false, true] {
+            let out = Command::new("sh", crate::ReturnStdoutAndErr)
+                .with_arguments([
+                    "-c",
+                    "head -c 300000 /dev/zero >&2; head -c 200000 /dev/zero; echo end >&2",
+                ])
+                .with_new_process_group(new_process_group)
+                .run()
+                .unwrap();
+
+            assert_eq!(out.stdout.len(), 200_000);
+            assert_eq!(out.stderr.len(), 300_004);
+            assert!(out.stderr.ends_with(b"end\n"));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn with_new_session_kills_left_over_processes() {
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments(["-c", "sleep 600 & echo $! $(cut -d ' ' -f 6 /proc/$$/stat)"])
+            .with_new_session(true)
+            .run()
+            .unwrap();
+
+        let mut parts = out.split_whitespace();
+        let pid: libc::pid_t = parts.next().unwrap().parse().unwrap();
+        let sid: libc::pid_t = parts.next().unwrap().parse().unwrap();
+        assert_ne!(sid, unsafe { libc::getsid(0) });
+        assert_process_terminates(pid);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn with_parent_death_signal_kills_the_child_if_the_spawning_thread_dies() {
+        let pid = thread::spawn(|| {
+            let cmd = Command::new("sleep", ReturnStdout)
+                .with_argument("600")
+                .with_parent_death_signal(Some(libc::SIGKILL));
+            // dropping a `std::process::Child` neither kills nor waits for it
+            create_sys_command(&cmd, false, false)
+                .unwrap()
+                .command
+                .spawn()
+                .unwrap()
+                .id()
+        })
+        .join()
+        .unwrap() as libc::pid_t;
+
+        assert_process_terminates(pid);
+        unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) };
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn dropping_a_child_guard_kills_the_child() {
+        let cmd = Command::new("sleep", ReturnStdout).with_argument("600");
+        let child = create_sys_command(&cmd, false, false)
+            .unwrap()
+            .command
+            .spawn()
+            .unwrap();
+        let pid = child.id() as libc::pid_t;
+
+        drop(ChildGuard::new(child, false));
+        assert_process_terminates(pid);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn dropping_a_child_guard_kills_the_process_group() {
+        let cmd = Command::new("sh", ReturnStdout)
+            .with_arguments(["-c", "sleep 600 & echo $!; wait"])
+            .with_new_process_group(true);
+        let mut child = ChildGuard::new(
+            create_sys_command(&cmd, true, false)
+                .unwrap()
+                .command
+                .spawn()
+                .unwrap(),
+            true,
+        );
+        let mut line = String::new();
+        io::BufRead::read_line(
+            &mut io::BufReader::new(child.child.stdout.as_mut().unwrap()),
+            &mut line,
+        )
+        .unwrap();
+
+        drop(child);
+        assert_process_terminates(line.trim().parse().unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn child_guards_track_the_child_through_a_pidfd() {
+        let cmd = Command::new("sleep", ReturnStdout).with_argument("600");
+        let child = ChildGuard::new(
+            create_sys_command(&cmd, false, false)
+                .unwrap()
+                .command
+                .spawn()
+                .unwrap(),
+            false,
+        );
+
+        assert!(child.pidfd.is_some());
+    }
+
+    #[cfg(unix)]
+    fn assert_wait_timeout_and_signals_work(mut child: ChildGuard) {
+        let status = child.wait_timeout(Some(Duration::from_millis(50))).unwrap();
+        assert!(status.is_none());
+
+        child.send_signal(libc::SIGTERM).unwrap();
+        let status = child.wait_timeout(Some(Duration::from_secs(10))).unwrap();
+        let status = map_std_exit_status(status.unwrap());
+        assert_eq!(
+            status,
+            ExitStatus::OsSpecific(OpaqueOsExitStatus::from_signal_number(libc::SIGTERM))
+        );
+
+        let err = child.send_signal(libc::SIGTERM).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn child_guards_can_wait_with_timeout_and_send_signals() {
+        let cmd = Command::new("sleep", ReturnStdout).with_argument("600");
+        let child = ChildGuard::new(
+            create_sys_command(&cmd, false, false)
+                .unwrap()
+                .command
+                .spawn()
+                .unwrap(),
+            false,
+        );
+
+        assert_wait_timeout_and_signals_work(child);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn child_guards_fall_back_to_pids_without_pidfd() {
+        let cmd = Command::new("sleep", ReturnStdout).with_argument("600");
+        let mut child = ChildGuard::new(
+            create_sys_command(&cmd, false, false)
+                .unwrap()
+                .command
+                .spawn()
+                .unwrap(),
+            false,
+        );
+        child.pidfd = None;
+
+        assert_wait_timeout_and_signals_work(child);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn forwarded_signals_are_relayed_to_the_child() {
+        let exit_status = Command::new("sh", crate::ReturnNothing)
+            .with_arguments(["-c", "kill -USR1 $PPID; exec sleep 600"])
+            .with_forwarded_signals(vec![libc::SIGUSR1])
+            .with_expected_exit_status(OpaqueOsExitStatus::from_signal_number(libc::SIGUSR1))
+            .run();
+
+        exit_status.unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn forwarded_signals_are_relayed_to_the_process_group() {
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments([
+                "-c",
+                "trap 'echo forwarded; exit 3' USR2; sleep 600 & kill -USR2 $PPID; wait",
+            ])
+            .with_forwarded_signals(vec![libc::SIGUSR2])
+            .with_new_process_group(true)
+            .with_expected_exit_status(3)
+            .run()
+            .unwrap();
+
+        assert_eq!(out, "forwarded\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn handled_forwarded_signals_are_reported() {
+        let cmd = || {
+            Command::new("sh", crate::ReturnNothing)
+                .with_arguments([
+                    "-c",
+                    "trap 'exit 3' WINCH; sleep 600 & kill -WINCH $PPID; wait",
+                ])
+                .with_forwarded_signals(vec![libc::SIGWINCH])
+                .with_new_process_group(true)
+        };
+
+        let result = actual_exec_exec_replacement_callback(cmd(), &crate::ReturnNothing).unwrap();
+        assert_eq!(result.exit_status, ExitStatus::Code(3));
+        assert_eq!(result.forwarded_signals, [libc::SIGWINCH]);
+
+        let err = match cmd().run().unwrap_err() {
+            crate::CommandExecutionError::UnexpectedExitStatus(err) => err,
+            other => panic!("unexpected error: {:?}", other),
+        };
+        assert_eq!(err.forwarded_signals(), [libc::SIGWINCH]);
+        assert!(err
+            .to_string()
+            .ends_with(&format!("Forwarded signals: [{}]", libc::SIGWINCH)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn forwarding_uncatchable_signals_fails() {
+        Command::new("true", crate::ReturnNothing)
+            .with_forwarded_signals(vec![libc::SIGKILL])
+            .run()
+            .unwrap_err();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn with_uid_gid_and_groups() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments(["-c", "id -u; id -g; id -G"])
+            .with_uid(Some(65534))
+            .with_gid(Some(65533))
+            .with_supplementary_groups(Some(vec![65533, 65532]))
+            .run()
+            .unwrap();
+
+        assert_eq!(out, "65534\n65533\n65533 65532\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn with_uid_drops_supplementary_groups_of_root() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let out = Command::new("id", crate::ReturnStdoutString)
+            .with_argument("-G")
+            .with_uid(Some(65534))
+            .with_gid(Some(65534))
+            .run()
+            .unwrap();
+
+        assert_eq!(out, "65534\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_to_switch_credentials_produces_a_descriptive_error() {
+        let cmd = Command::new("true", crate::ReturnNothing);
+        let cmd = if unsafe { libc::geteuid() } == 0 {
+            // exceeds NGROUPS_MAX
+            cmd.with_supplementary_groups(Some(vec![1; 1 << 17]))
+        } else {
+            cmd.with_uid(Some(0))
+        };
+
+        let err = match cmd.run().unwrap_err() {
+            crate::CommandExecutionError::Io(err) => err,
+            other => panic!("unexpected error: {:?}", other),
+        };
+        let err = err
+            .get_ref()
+            .and_then(|err| err.downcast_ref::<SwitchCredentialsError>())
+            .unwrap();
+        assert!(err.to_string().starts_with("Spawning with credentials"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn other_spawn_errors_are_not_blamed_on_switching_credentials() {
+        let hook = unsafe {
+            crate::PreExecHook::new("fail", || Err(io::Error::from_raw_os_error(libc::EPERM)))
+        };
+        let cmd = || {
+            Command::new("true", crate::ReturnNothing)
+                .with_uid(Some(unsafe { libc::getuid() }))
+                .with_gid(Some(unsafe { libc::getgid() }))
+        };
+
+        for cmd in [
+            cmd().with_pre_exec_hook(hook),
+            cmd().with_working_directory_override(Some("/does/not/exist")),
+        ] {
+            let err = match cmd.run().unwrap_err() {
+                crate::CommandExecutionError::Io(err) => err,
+                other => panic!("unexpected error: {:?}", other),
+            };
+            assert!(err.raw_os_error().is_some(), "{:?}", err);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn with_rlimit_sets_limits_in_the_child() {
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments(["-c", "ulimit -Sn; ulimit -Hn; ulimit -c"])
+            .with_rlimit(Resource::OpenFiles, 64, 128)
+            .with_rlimit(Resource::CoreSize, 0, 0)
+            .run()
+            .unwrap();
+
+        assert_eq!(out, "64\n128\n0\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn with_rlimit_is_applied_before_switching_credentials() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let mut current = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(
+            unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut current) },
+            0
+        );
+        let max = std::fs::read_to_string("/proc/sys/fs/nr_open").unwrap();
+        if current.rlim_max >= max.trim().parse().unwrap() {
+            return;
+        }
+
+        // Raising the hard limit is only possible with the privileges of root.
+        #[allow(clippy::useless_conversion)] // `rlim_t` is only 32 bits on some targets
+        let raised = u64::from(current.rlim_max) + 1;
+        let cmd = || {
+            Command::new("sh", crate::ReturnStdoutString)
+                .with_arguments(["-c", "ulimit -Hn"])
+                .with_rlimit(Resource::OpenFiles, 64, raised)
+        };
+        // Some sandboxes deny raising limits even to root.
+        if cmd().run().is_err() {
+            return;
+        }
+        let out = cmd().with_uid(Some(65534)).run().unwrap();
+
+        assert_eq!(out, format!("{}\n", raised));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn exceeding_the_cpu_limit_is_classified_in_the_exit_status() {
+        let result = actual_exec_exec_replacement_callback(
+            Command::new("sh", crate::ReturnNothing)
+                .with_arguments(["-c", "while :; do :; done"])
+                .with_rlimit(Resource::Cpu, 1, ResourceLimit::UNLIMITED),
+            &crate::ReturnNothing,
+        )
+        .unwrap();
+        assert_eq!(
+            result.exit_status.resource_limit_violation(),
+            Some(Resource::Cpu)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn child_setup_settings_are_applied() {
+        // Increasing the nice value never needs privileges.
+        let niceness = (unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) } + 1).min(19);
+        // Only CPUs of the current CPU set can be used.
+        let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of_val(&cpu_set);
+        assert_eq!(unsafe { libc::sched_getaffinity(0, size, &mut cpu_set) }, 0);
+        let cpu = (0..size * 8)
+            .find(|&cpu| unsafe { libc::CPU_ISSET(cpu, &cpu_set) })
+            .unwrap();
+
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments([
+                "-c",
+                "umask; nice; grep Cpus_allowed_list /proc/self/status; tr '\\0' ' ' </proc/$$/cmdline",
+            ])
+            .with_umask(Some(0o027))
+            .with_niceness(Some(niceness))
+            .with_cpu_affinity(Some(vec![cpu]))
+            .with_arg0(Some("custom-name"))
+            .run()
+            .unwrap();
+
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "0027");
+        assert_eq!(lines[1], niceness.to_string());
+        assert_eq!(lines[2], format!("Cpus_allowed_list:\t{}", cpu));
+        assert!(lines[3].starts_with("custom-name -c "), "{:?}", lines[3]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn with_niceness_is_applied_before_switching_credentials() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let cmd = || Command::new("nice", crate::ReturnStdoutString).with_niceness(Some(-5));
+        // Some sandboxes deny lowering the niceness even to root.
+        if cmd().run().is_err() {
+            return;
+        }
+        let out = cmd().with_uid(Some(65534)).run().unwrap();
+
+        assert_eq!(out, "-5\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pre_exec_hooks_run_after_other_settings() {
+        let hook = unsafe {
+            crate::PreExecHook::new("umask", || {
+                libc::umask(0o077);
+                Ok(())
+            })
+        };
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments(["-c", "umask"])
+            .with_umask(Some(0o022))
+            .with_pre_exec_hook(hook)
+            .run()
+            .unwrap();
+
+        assert_eq!(out, "0077\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_pre_exec_hooks_fail_the_command() {
+        let hook = unsafe {
+            crate::PreExecHook::new("fail", || Err(io::Error::from_raw_os_error(libc::EPERM)))
+        };
+        let err = match Command::new("true", crate::ReturnNothing)
+            .with_pre_exec_hook(hook)
+            .run()
+            .unwrap_err()
+        {
+            crate::CommandExecutionError::Io(err) => err,
+            other => panic!("unexpected error: {:?}", other),
+        };
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn invalid_cpu_affinity_fails_the_command() {
+        let result = Command::new("true", crate::ReturnNothing)
+            .with_cpu_affinity(Some(vec![1 << 20]))
+            .run();
+        assert!(result.is_err());
+    }
+
+    #[cfg(unix)]
+    fn pipe_with_content(content: &str) -> std::os::unix::io::OwnedFd {
+        use std::{
+            fs::File,
+            io::Write,
+            os::unix::io::{FromRawFd, OwnedFd},
+        };
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let (read, mut write) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        write.write_all(content.as_bytes()).unwrap();
+        read
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn inherited_fds_are_available_at_their_target_number() {
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments(["-c", "cat <&3; cat <&7"])
+            .with_inherited_fd(3, pipe_with_content("hy "))
+            .with_inherited_fd(7, pipe_with_content("there"))
+            .run()
+            .unwrap();
+
+        assert_eq!(out, "hy there");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn inherited_fds_can_swap_numbers() {
+        use std::os::unix::io::AsRawFd;
+        let (a, b) = (pipe_with_content("a"), pipe_with_content("b"));
+        let (raw_a, raw_b) = (a.as_raw_fd(), b.as_raw_fd());
+        let script = format!("cat <&{}; cat <&{}", raw_a, raw_b);
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments(["-c", &script])
+            .with_inherited_fd(raw_b, a)
+            .with_inherited_fd(raw_a, b)
+            .run()
+            .unwrap();
+
+        assert_eq!(out, "ba");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pty_output_is_captured_as_stdout() {
+        let out = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments([
+                "-c",
+                "test -t 0 && test -t 1 && test -t 2 && echo tty; stty size; echo err >&2",
+            ])
+            .with_pty(Some(crate::PtySize {
+                rows: 42,
+                cols: 123,
+            }))
+            .run()
+            .unwrap();
+
+        assert_eq!(out, "tty\r\n42 123\r\nerr\r\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pty_output_is_drained_if_not_captured() {
+        let result = Command::new("sh", crate::ReturnNothing)
+            .with_arguments(["-c", "head -c 1000000 /dev/zero"])
+            .with_pty(Some(crate::PtySize::default()))
+            .run();
+
+        result.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_unchanged_env_is_inherited_as_is() {
+        let cmd =
+            Command::new("sh", crate::ReturnStdoutString).with_arguments(["-c", "echo \"$PATH\""]);
+        let sys_cmd = create_sys_command(&cmd, true, false).unwrap().command;
+        assert_eq!(sys_cmd.get_envs().count(), 0);
+
+        let out = cmd.run().unwrap();
+        assert_eq!(out.trim_end(), std::env::var("PATH").unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn env_updates_are_applied_on_top_of_the_inherited_env() {
+        let cmd = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments(["-c", "echo \"$PATH $FOO ${HOME-unset}\""])
+            .with_env_update("FOO", "bar")
+            .with_env_update("HOME", EnvChange::Remove)
+            .with_env_update("PATH", EnvChange::Append("/mapped-command/bin".into()));
+        let sys_cmd = create_sys_command(&cmd, true, false).unwrap().command;
+        assert_eq!(sys_cmd.get_envs().count(), 3);
+
+        let path = crate::join_path_lists(
+            std::env::var_os("PATH").as_deref(),
+            Some("/mapped-command/bin".as_ref()),
+        );
+        let out = cmd.run().unwrap();
+        assert_eq!(
+            out.trim_end(),
+            format!("{} bar unset", path.to_str().unwrap())
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_filtered_env_is_rebuilt() {
+        let cmd = Command::new("sh", crate::ReturnStdoutString)
+            .with_arguments(["-c", "echo \"$PATH $FOO ${HOME-unset}\""])
+            .with_env_inherit_filter(["PATH"])
+            .with_env_update("FOO", "bar");
+        let sys_cmd = create_sys_command(&cmd, true, false).unwrap().command;
+        assert_eq!(sys_cmd.get_envs().count(), 2);
+
+        let out = cmd.run().unwrap();
+        assert_eq!(
+            out.trim_end(),
+            format!("{} bar unset", std::env::var("PATH").unwrap())
+        );
+    }
+
+    /// Waits a bit for given process to terminate, zombies count as terminated.
+    #[cfg(target_os = "linux")]
+    fn assert_process_terminates(pid: libc::pid_t) {
+        for _ in 0..100 {
+            match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+                Err(_) => return,
+                Ok(stat)
+                    if stat
+                        .rsplit(')')
+                        .next()
+                        .unwrap()
+                        .trim_start()
+                        .starts_with('Z') =>
+                {
+                    return
+                }
+                Ok(_) => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        }
+        panic!("process {} was not terminated", pid);
+    }
+
     #[test]
     fn special_windows_exit_code_cast() {
         assert_eq!(windows_cast_exit_code(-1), u32::MAX as i64);
@@ -213,7 +1665,7 @@ mod tests {
                 //      if it has a signal as if stopped and the coredumped flag set. So we can't do an
                 //      assert(libc::WIFSIGNALED(raw_status) || libc::WIFSTOPPED(raw_status))
                 let signal = libc::WTERMSIG(raw_status);
-                assert_eq!(exit_status, ExitStatus::OsSpecific(OpaqueOsExitStatus::from_signal_number(signal).into()));
+                assert_eq!(exit_status, ExitStatus::OsSpecific(OpaqueOsExitStatus::from_signal_number(signal)));
             }
         }
     }
@@ -0,0 +1,983 @@
+//! Unix specific settings of a [`Command`].
+#[cfg(target_os = "linux")]
+use crate::SessionScript;
+use crate::{Command, ExitStatus, UnexpectedExitStatus};
+use std::{
+    ffi::{OsStr, OsString},
+    fmt, io,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    sync::Arc,
+};
+use thiserror::Error;
+
+/// Unix specific settings stored in a [`Command`].
+#[derive(Debug, Default)]
+pub(crate) struct UnixSettings {
+    pub(crate) new_process_group: bool,
+    pub(crate) new_session: bool,
+    #[cfg(target_os = "linux")]
+    pub(crate) parent_death_signal: Option<i32>,
+    pub(crate) forwarded_signals: Vec<i32>,
+    pub(crate) uid: Option<u32>,
+    pub(crate) gid: Option<u32>,
+    pub(crate) supplementary_groups: Option<Vec<u32>>,
+    pub(crate) resource_limits: Vec<ResourceLimit>,
+    pub(crate) umask: Option<u32>,
+    pub(crate) arg0: Option<OsString>,
+    pub(crate) niceness: Option<i32>,
+    #[cfg(target_os = "linux")]
+    pub(crate) cpu_affinity: Option<Vec<usize>>,
+    pub(crate) pre_exec_hooks: Vec<PreExecHook>,
+    pub(crate) inherited_fds: Vec<InheritedFd>,
+    #[cfg(target_os = "linux")]
+    pub(crate) pty: Option<PtySize>,
+    #[cfg(target_os = "linux")]
+    pub(crate) session_script: Option<SessionScript>,
+}
+
+impl<Output, Error> Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    /// Returns true if the sub-process will be placed in a new process group.
+    ///
+    /// The sub-process will be the leader of the new process group and all
+    /// processes it spawns will (by default) be in the same process group.
+    ///
+    /// Once the sub-process exited all processes which are still in the process
+    /// group are killed (`SIGKILL`). This means helper processes spawned by the
+    /// sub-process (e.g. by `make -j`) will not outlive the command.
+    ///
+    /// This is also true if [`Command::new_session()`] is `true`, as a new
+    /// session always implies a new process group.
+    ///
+    /// *This is only available on unix.*
+    pub fn new_process_group(&self) -> bool {
+        self.unix_settings.new_process_group
+    }
+
+    /// Sets if the sub-process should be placed in a new process group.
+    ///
+    /// See [`Command::new_process_group()`].
+    pub fn with_new_process_group(mut self, new_process_group: bool) -> Self {
+        self.unix_settings.new_process_group = new_process_group;
+        self
+    }
+
+    /// Returns true if the sub-process will be placed in a new session (`setsid`).
+    ///
+    /// The sub-process will be the leader of the new session and of a new
+    /// process group in that session, it also won't have a controlling terminal.
+    ///
+    /// Like with [`Command::new_process_group()`] all processes still in the
+    /// process group once the sub-process exited are killed.
+    ///
+    /// *This is only available on unix.*
+    pub fn new_session(&self) -> bool {
+        self.unix_settings.new_session
+    }
+
+    /// Sets if the sub-process should be placed in a new session.
+    ///
+    /// See [`Command::new_session()`].
+    pub fn with_new_session(mut self, new_session: bool) -> Self {
+        self.unix_settings.new_session = new_session;
+        self
+    }
+
+    /// Returns the signal the sub-process will receive if the spawning thread dies.
+    ///
+    /// This uses `prctl(PR_SET_PDEATHSIG, signal)`. Be aware that on linux this is
+    /// bound to the *thread* which spawned the sub-process and not the process. As
+    /// [`Command::run()`] blocks the calling thread until the sub-process exited
+    /// this normally means the sub-process is killed if the spawning process dies
+    /// (e.g. crashes).
+    ///
+    /// By default this is `None`, i.e. the sub-process keeps running.
+    ///
+    /// *This is only available on linux.*
+    #[cfg(target_os = "linux")]
+    pub fn parent_death_signal(&self) -> Option<i32> {
+        self.unix_settings.parent_death_signal
+    }
+
+    /// Sets the signal the sub-process will receive if the spawning thread dies.
+    ///
+    /// E.g. use `with_parent_death_signal(Some(libc::SIGKILL))` to make sure
+    /// no sub-process outlives a crashing daemon.
+    ///
+    /// See [`Command::parent_death_signal()`].
+    #[cfg(target_os = "linux")]
+    pub fn with_parent_death_signal(mut self, signal: Option<i32>) -> Self {
+        self.unix_settings.parent_death_signal = signal;
+        self
+    }
+
+    /// Returns the signals which will be forwarded to the sub-process while it runs.
+    ///
+    /// While [`Command::run()`] waits for the sub-process signal handlers for this
+    /// signals are installed in the current process. Each caught signal is relayed
+    /// to the sub-process (or its process group if [`Command::new_process_group()`]
+    /// or [`Command::new_session()`] is set) and then `run` continues to wait for the
+    /// sub-process. The previous signal handlers are restored once the sub-process
+    /// exited.
+    ///
+    /// If the sub-process is terminated by the forwarded signal this will be
+    /// reflected in the resulting exit status, e.g. as `signal(2)` for `SIGINT`.
+    /// If the sub-process handles the signal and exits with an exit code that
+    /// exit code will be returned. In both cases the relayed signals are listed in
+    /// [`ExecResult::forwarded_signals`](crate::ExecResult::forwarded_signals) and
+    /// [`UnexpectedExitStatus::forwarded_signals()`].
+    ///
+    /// Signals caught before the sub-process was spawned are relayed once it was
+    /// spawned. If spawning fails they are raised again in the current process after
+    /// the previous signal handlers were restored.
+    ///
+    /// By default this is empty, i.e. no signals are forwarded.
+    ///
+    /// *This is only available on unix.*
+    pub fn forwarded_signals(&self) -> &[i32] {
+        &self.unix_settings.forwarded_signals
+    }
+
+    /// Sets the signals which should be forwarded to the sub-process.
+    ///
+    /// E.g. `with_forwarded_signals(vec![libc::SIGINT, libc::SIGTERM])`.
+    ///
+    /// Signals which can't be caught (e.g. `SIGKILL`) will make running the
+    /// command fail with an `io::Error`.
+    ///
+    /// See [`Command::forwarded_signals()`].
+    pub fn with_forwarded_signals(mut self, signals: impl IntoIterator<Item = i32>) -> Self {
+        self.unix_settings.forwarded_signals = signals.into_iter().collect();
+        self
+    }
+
+    /// Returns the user id the sub-process will run as.
+    ///
+    /// If `None` (the default) the user id is inherited from the current process.
+    ///
+    /// If the current process runs as root and no supplementary groups are set
+    /// switching the user id will clear all supplementary groups, so that no
+    /// privileges are retained through them.
+    ///
+    /// If switching fails (e.g. because the current process is not privileged)
+    /// running the command fails with an `io::Error` wrapping a
+    /// [`SwitchCredentialsError`].
+    ///
+    /// *This is only available on unix.*
+    pub fn uid(&self) -> Option<u32> {
+        self.unix_settings.uid
+    }
+
+    /// Sets the user id the sub-process will run as.
+    ///
+    /// See [`Command::uid()`].
+    pub fn with_uid(mut self, uid: Option<u32>) -> Self {
+        self.unix_settings.uid = uid;
+        self
+    }
+
+    /// Returns the group id the sub-process will run as.
+    ///
+    /// If `None` (the default) the group id is inherited from the current process.
+    ///
+    /// See [`Command::uid()`] for what happens if switching fails.
+    ///
+    /// *This is only available on unix.*
+    pub fn gid(&self) -> Option<u32> {
+        self.unix_settings.gid
+    }
+
+    /// Sets the group id the sub-process will run as.
+    ///
+    /// See [`Command::gid()`].
+    pub fn with_gid(mut self, gid: Option<u32>) -> Self {
+        self.unix_settings.gid = gid;
+        self
+    }
+
+    /// Returns the supplementary groups the sub-process will have.
+    ///
+    /// If `None` (the default) the supplementary groups are inherited from the
+    /// current process, except if the process runs as root and [`Command::uid()`]
+    /// is set (see there). `Some(&[])` explicitly clears all supplementary groups.
+    ///
+    /// See [`Command::uid()`] for what happens if switching fails.
+    ///
+    /// *This is only available on unix.*
+    pub fn supplementary_groups(&self) -> Option<&[u32]> {
+        self.unix_settings.supplementary_groups.as_deref()
+    }
+
+    /// Sets the supplementary groups the sub-process will have.
+    ///
+    /// See [`Command::supplementary_groups()`].
+    pub fn with_supplementary_groups(mut self, groups: Option<Vec<u32>>) -> Self {
+        self.unix_settings.supplementary_groups = groups;
+        self
+    }
+
+    /// Returns the resource limits (`setrlimit`) which will be applied to the sub-process.
+    ///
+    /// The limits are set in the sub-process before the program is executed, resources
+    /// not listed here keep the limits inherited from the current process. They are set
+    /// before the credentials are switched (see [`Command::uid()`]), so the privileges of
+    /// the current process apply.
+    ///
+    /// Violating a limit is normally reflected in the exit status of the sub-process,
+    /// e.g. exceeding the soft CPU time limit sends a `SIGXCPU`. See
+    /// [`ExitStatus::resource_limit_violation()`].
+    ///
+    /// *This is only available on unix.*
+    pub fn resource_limits(&self) -> &[ResourceLimit] {
+        &self.unix_settings.resource_limits
+    }
+
+    /// Returns this command with a resource limit added (or replaced).
+    ///
+    /// If a limit for given resource was already set it is replaced.
+    ///
+    /// Use [`ResourceLimit::UNLIMITED`] for no limit. The soft limit must not be
+    /// larger than the hard limit and without privileges the hard limit can't be
+    /// raised, else running the command will fail with an `io::Error`. It also fails
+    /// with an `io::Error` of kind `InvalidInput` if a value doesn't fit into the
+    /// `rlim_t` of the target.
+    ///
+    /// See [`Command::resource_limits()`].
+    pub fn with_rlimit(mut self, resource: Resource, soft: u64, hard: u64) -> Self {
+        let limits = &mut self.unix_settings.resource_limits;
+        limits.retain(|limit| limit.resource != resource);
+        limits.push(ResourceLimit {
+            resource,
+            soft,
+            hard,
+        });
+        self
+    }
+
+    /// Returns the umask the sub-process will have.
+    ///
+    /// If `None` (the default) the umask is inherited from the current process.
+    ///
+    /// *This is only available on unix.*
+    pub fn umask(&self) -> Option<u32> {
+        self.unix_settings.umask
+    }
+
+    /// Sets the umask the sub-process will have.
+    ///
+    /// See [`Command::umask()`].
+    pub fn with_umask(mut self, umask: Option<u32>) -> Self {
+        self.unix_settings.umask = umask;
+        self
+    }
+
+    /// Returns the value passed as first argument (`argv[0]`) to the program.
+    ///
+    /// If `None` (the default) the program is passed as first argument.
+    ///
+    /// *This is only available on unix.*
+    pub fn arg0(&self) -> Option<&OsStr> {
+        self.unix_settings.arg0.as_deref()
+    }
+
+    /// Sets the value passed as first argument (`argv[0]`) to the program.
+    ///
+    /// See [`Command::arg0()`].
+    pub fn with_arg0(mut self, arg0: Option<impl Into<OsString>>) -> Self {
+        self.unix_settings.arg0 = arg0.map(Into::into);
+        self
+    }
+
+    /// Returns the nice value (scheduling priority) the sub-process will have.
+    ///
+    /// This is the absolute nice value (`setpriority`), not an increment. Higher
+    /// values mean a lower priority. If `None` (the default) the nice value is
+    /// inherited from the current process.
+    ///
+    /// Lowering the nice value below the one of the current process normally
+    /// requires privileges, if it fails running the command fails with an `io::Error`.
+    /// The nice value is set before the credentials are switched (see [`Command::uid()`]),
+    /// so the privileges of the current process are used.
+    ///
+    /// *This is only available on unix.*
+    pub fn niceness(&self) -> Option<i32> {
+        self.unix_settings.niceness
+    }
+
+    /// Sets the nice value (scheduling priority) the sub-process will have.
+    ///
+    /// See [`Command::niceness()`].
+    pub fn with_niceness(mut self, niceness: Option<i32>) -> Self {
+        self.unix_settings.niceness = niceness;
+        self
+    }
+
+    /// Returns the CPUs the sub-process is allowed to run on.
+    ///
+    /// CPUs are identified by their index as used by `sched_setaffinity`. If
+    /// `None` (the default) the CPU affinity is inherited from the current process.
+    ///
+    /// If none of the CPUs is available, or an index is too large, running the command
+    /// fails with an `io::Error`.
+    ///
+    /// *This is only available on linux.*
+    #[cfg(target_os = "linux")]
+    pub fn cpu_affinity(&self) -> Option<&[usize]> {
+        self.unix_settings.cpu_affinity.as_deref()
+    }
+
+    /// Sets the CPUs the sub-process is allowed to run on.
+    ///
+    /// See [`Command::cpu_affinity()`].
+    #[cfg(target_os = "linux")]
+    pub fn with_cpu_affinity(mut self, cpus: Option<Vec<usize>>) -> Self {
+        self.unix_settings.cpu_affinity = cpus;
+        self
+    }
+
+    /// Returns the hooks which will run in the sub-process before the program is executed.
+    ///
+    /// The hooks run in the order they were added, after all other settings
+    /// (e.g. [`Command::uid()`] or [`Command::umask()`]) have been applied.
+    ///
+    /// *This is only available on unix.*
+    pub fn pre_exec_hooks(&self) -> &[PreExecHook] {
+        &self.unix_settings.pre_exec_hooks
+    }
+
+    /// Returns this command with an additional pre-exec hook.
+    ///
+    /// This is an escape hatch for child setup not covered by the other settings,
+    /// see [`PreExecHook::new()`] for the (many) restrictions of such hooks.
+    ///
+    /// See [`Command::pre_exec_hooks()`].
+    pub fn with_pre_exec_hook(mut self, hook: PreExecHook) -> Self {
+        self.unix_settings.pre_exec_hooks.push(hook);
+        self
+    }
+
+    /// Returns the additional file descriptors the sub-process will inherit.
+    ///
+    /// Each file descriptor is made available in the sub-process under its
+    /// [`InheritedFd::target_fd()`] number (independent of its number in the current
+    /// process), e.g. for `gpg --passphrase-fd 3` or socket activation.
+    ///
+    /// The file descriptors are owned by the command and closed in the current
+    /// process once the command is dropped.
+    ///
+    /// *This is only available on unix.*
+    pub fn inherited_fds(&self) -> &[InheritedFd] {
+        &self.unix_settings.inherited_fds
+    }
+
+    /// Returns this command with an additional inherited file descriptor.
+    ///
+    /// If a file descriptor was already mapped to `target_fd` it is replaced.
+    ///
+    /// Mapping to `0`, `1` or `2` overrides the stdin/stdout/stderr setup of the command,
+    /// which e.g. breaks capturing the output, so this should normally be avoided.
+    ///
+    /// See [`Command::inherited_fds()`].
+    pub fn with_inherited_fd(mut self, target_fd: RawFd, fd: OwnedFd) -> Self {
+        let fds = &mut self.unix_settings.inherited_fds;
+        fds.retain(|inherited| inherited.target_fd != target_fd);
+        fds.push(InheritedFd { target_fd, fd });
+        self
+    }
+
+    /// Returns the window size of the pseudo-terminal the sub-process will run in, if any.
+    ///
+    /// If `Some` the sub-process gets a new pseudo-terminal as its stdin, stdout and
+    /// stderr. This is useful for programs which behave differently (e.g. colorize
+    /// their output) or refuse to run if not connected to a terminal.
+    ///
+    /// - The sub-process runs in a new session with the pseudo-terminal as controlling
+    ///   terminal, so this implies [`Command::new_session()`] (including killing all
+    ///   processes left in the process group once the sub-process exited).
+    /// - All terminal output (stdout and stderr combined) is passed to the
+    ///   [`OutputMapping`](crate::OutputMapping) as stdout (if it captures stdout),
+    ///   stderr will always be empty. The output is raw terminal output, e.g. it
+    ///   normally uses `"\r\n"` line endings.
+    /// - Nothing is written to the terminal, so a sub-process reading from stdin will
+    ///   block.
+    ///
+    /// *This is only available on linux.*
+    #[cfg(target_os = "linux")]
+    pub fn pty(&self) -> Option<PtySize> {
+        self.unix_settings.pty
+    }
+
+    /// Sets if (and with which window size) the sub-process will run in a pseudo-terminal.
+    ///
+    /// See [`Command::pty()`].
+    #[cfg(target_os = "linux")]
+    pub fn with_pty(mut self, size: Option<PtySize>) -> Self {
+        self.unix_settings.pty = size;
+        self
+    }
+
+    /// Returns true if the sub-process will run with different credentials.
+    pub(crate) fn switches_credentials(&self) -> bool {
+        self.uid().is_some() || self.gid().is_some() || self.supplementary_groups().is_some()
+    }
+
+    /// Returns true if the sub-process will lead its own process group.
+    ///
+    /// This is the case if either a new process group or a new session is
+    /// created.
+    pub(crate) fn owns_process_group(&self) -> bool {
+        self.new_process_group() || self.creates_session()
+    }
+
+    /// Returns true if the sub-process will lead its own session.
+    pub(crate) fn creates_session(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        let pty = self.pty().is_some();
+        #[cfg(not(target_os = "linux"))]
+        let pty = false;
+        self.new_session() || pty
+    }
+}
+
+/// A resource which can be limited using [`Command::with_rlimit()`].
+///
+/// The units are the same as for `setrlimit`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Resource {
+    /// CPU time in seconds (`RLIMIT_CPU`).
+    ///
+    /// Exceeding the soft limit sends `SIGXCPU`, exceeding the hard limit `SIGKILL`.
+    Cpu,
+    /// The maximal size of files the process creates in bytes (`RLIMIT_FSIZE`).
+    ///
+    /// Exceeding the soft limit sends `SIGXFSZ`.
+    FileSize,
+    /// The maximal size of the data segment in bytes (`RLIMIT_DATA`).
+    Data,
+    /// The maximal size of the stack in bytes (`RLIMIT_STACK`).
+    Stack,
+    /// The maximal size of core dumps in bytes (`RLIMIT_CORE`).
+    ///
+    /// A limit of `0` disables core dumps.
+    CoreSize,
+    /// The maximal file descriptor number + 1 (`RLIMIT_NOFILE`).
+    OpenFiles,
+    /// The maximal size of the virtual memory (address space) in bytes (`RLIMIT_AS`).
+    AddressSpace,
+    /// The maximal number of processes of the real user id (`RLIMIT_NPROC`).
+    Processes,
+}
+
+/// A resource limit, see [`Command::with_rlimit()`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceLimit {
+    /// The limited resource.
+    pub resource: Resource,
+    /// The soft limit, i.e. the limit currently enforced.
+    pub soft: u64,
+    /// The hard limit, i.e. the upper bound to which the soft limit can be raised.
+    pub hard: u64,
+}
+
+impl ResourceLimit {
+    /// Value representing no limit (i.e. `RLIM_INFINITY`).
+    pub const UNLIMITED: u64 = u64::MAX;
+}
+
+impl ExitStatus {
+    /// Returns the resource whose limit was violated, if the exit status indicates so.
+    ///
+    /// This is based on the signal terminating the process, so it only recognizes
+    /// violations of limits which are enforced by a dedicated signal, i.e.
+    /// [`Resource::Cpu`] (`SIGXCPU`) and [`Resource::FileSize`] (`SIGXFSZ`). Other
+    /// violations manifest themselves e.g. as failing allocations or a `SIGKILL`
+    /// which can't be distinguished from other causes.
+    ///
+    /// *This is only available on unix.*
+    pub fn resource_limit_violation(&self) -> Option<Resource> {
+        match self {
+            ExitStatus::OsSpecific(status) => match status.signal_number() {
+                libc::SIGXCPU => Some(Resource::Cpu),
+                libc::SIGXFSZ => Some(Resource::FileSize),
+                _ => None,
+            },
+            ExitStatus::Code(_) => None,
+        }
+    }
+}
+
+/// The window size of a pseudo-terminal, see [`Command::with_pty()`].
+#[cfg(target_os = "linux")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PtySize {
+    /// The number of rows (lines).
+    pub rows: u16,
+    /// The number of columns.
+    pub cols: u16,
+}
+
+#[cfg(target_os = "linux")]
+impl Default for PtySize {
+    /// The classic 24x80 terminal size.
+    fn default() -> Self {
+        PtySize { rows: 24, cols: 80 }
+    }
+}
+
+/// A file descriptor passed to the sub-process, see [`Command::with_inherited_fd()`].
+#[derive(Debug)]
+pub struct InheritedFd {
+    target_fd: RawFd,
+    fd: OwnedFd,
+}
+
+impl InheritedFd {
+    /// Returns the number of the file descriptor in the sub-process.
+    pub fn target_fd(&self) -> RawFd {
+        self.target_fd
+    }
+
+    /// Returns the file descriptor (in the current process).
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+
+    /// Returns the raw file descriptor (in the current process).
+    pub(crate) fn raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// A named hook run in the sub-process between fork and exec.
+///
+/// See [`Command::with_pre_exec_hook()`].
+#[derive(Clone)]
+pub struct PreExecHook {
+    name: String,
+    hook: Arc<dyn Fn() -> io::Result<()> + Send + Sync>,
+}
+
+impl PreExecHook {
+    /// Creates a new pre-exec hook.
+    ///
+    /// The name is used to identify the hook, e.g. when inspecting a command
+    /// in a exec replacement callback or in debug output.
+    ///
+    /// If the hook returns an error running the command fails with that error.
+    ///
+    /// # Safety
+    ///
+    /// The hook runs in the forked sub-process, i.e. it has the same restrictions
+    /// as closures passed to `std::os::unix::process::CommandExt::pre_exec`:
+    /// only async-signal-safe operations may be done, which e.g. excludes
+    /// allocating memory or acquiring locks. Resources (like file descriptors)
+    /// are shared with the current process.
+    pub unsafe fn new(
+        name: impl Into<String>,
+        hook: impl Fn() -> io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        PreExecHook {
+            name: name.into(),
+            hook: Arc::new(hook),
+        }
+    }
+
+    /// Returns the name of this hook.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the function run in the sub-process.
+    pub(crate) fn hook(&self) -> Arc<dyn Fn() -> io::Result<()> + Send + Sync> {
+        self.hook.clone()
+    }
+}
+
+impl fmt::Debug for PreExecHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreExecHook")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Spawning the sub-process with changed credentials failed.
+///
+/// This is returned wrapped in an `io::Error` (with the same kind as the
+/// `io::Error` causing it) if spawning a command with [`Command::uid()`],
+/// [`Command::gid()`] or [`Command::supplementary_groups()`] set fails while
+/// switching the credentials. Other spawn errors are returned unchanged.
+#[derive(Debug, Error)]
+#[error("Spawning with credentials uid={uid:?}, gid={gid:?}, groups={groups:?} failed: {source}")]
+pub struct SwitchCredentialsError {
+    uid: Option<u32>,
+    gid: Option<u32>,
+    groups: Option<Vec<u32>>,
+    #[source]
+    source: io::Error,
+}
+
+impl SwitchCredentialsError {
+    pub(crate) fn new<O, E>(cmd: &Command<O, E>, source: io::Error) -> Self
+    where
+        E: From<io::Error> + From<UnexpectedExitStatus>,
+    {
+        SwitchCredentialsError {
+            uid: cmd.uid(),
+            gid: cmd.gid(),
+            groups: cmd.supplementary_groups().map(ToOwned::to_owned),
+            source,
+        }
+    }
+
+    /// Returns the user id which was supposed to be used.
+    pub fn uid(&self) -> Option<u32> {
+        self.uid
+    }
+
+    /// Returns the group id which was supposed to be used.
+    pub fn gid(&self) -> Option<u32> {
+        self.gid
+    }
+
+    /// Returns the supplementary groups which were supposed to be used.
+    pub fn supplementary_groups(&self) -> Option<&[u32]> {
+        self.groups.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod ExitStatus {
+        mod resource_limit_violation {
+            use crate::{ExitStatus, OpaqueOsExitStatus, Resource};
+
+            #[test]
+            fn classifies_signals_sent_on_limit_violations() {
+                let status =
+                    |signal| ExitStatus::from(OpaqueOsExitStatus::from_signal_number(signal));
+                assert_eq!(
+                    status(libc::SIGXCPU).resource_limit_violation(),
+                    Some(Resource::Cpu)
+                );
+                assert_eq!(
+                    status(libc::SIGXFSZ).resource_limit_violation(),
+                    Some(Resource::FileSize)
+                );
+                assert_eq!(status(libc::SIGKILL).resource_limit_violation(), None);
+                assert_eq!(
+                    ExitStatus::Code(libc::SIGXCPU as i64).resource_limit_violation(),
+                    None
+                );
+            }
+        }
+    }
+
+    mod Command {
+        mod process_group {
+            use crate::{Command, ExecResult, ReturnNothing};
+            use proptest::prelude::*;
+
+            #[test]
+            fn by_default_no_new_process_group_or_session_is_created() {
+                let cmd = Command::new("foo", ReturnNothing);
+                assert!(!cmd.new_process_group());
+                assert!(!cmd.new_session());
+            }
+
+            proptest! {
+                #[test]
+                fn process_group_settings_are_visible_to_the_exec_replacement_callback(
+                    new_process_group in proptest::bool::ANY,
+                    new_session in proptest::bool::ANY
+                ) {
+                    Command::new("foo", ReturnNothing)
+                        .with_new_process_group(new_process_group)
+                        .with_new_session(new_session)
+                        .with_exec_replacement_callback(move |cmd, _| {
+                            assert_eq!(cmd.new_process_group(), new_process_group);
+                            assert_eq!(cmd.new_session(), new_session);
+                            Ok(ExecResult::default())
+                        })
+                        .run()
+                        .unwrap();
+                }
+            }
+        }
+
+        mod resource_limits {
+            use crate::{Command, ExecResult, Resource, ResourceLimit, ReturnNothing};
+
+            #[test]
+            fn by_default_no_resource_limits_are_set() {
+                let cmd = Command::new("foo", ReturnNothing);
+                assert!(cmd.resource_limits().is_empty());
+            }
+
+            #[test]
+            fn setting_a_limit_for_the_same_resource_replaces_it() {
+                Command::new("foo", ReturnNothing)
+                    .with_rlimit(Resource::Cpu, 1, 2)
+                    .with_rlimit(Resource::OpenFiles, 64, 64)
+                    .with_rlimit(Resource::Cpu, 3, ResourceLimit::UNLIMITED)
+                    .with_exec_replacement_callback(|cmd, _| {
+                        assert_eq!(
+                            cmd.resource_limits(),
+                            &[
+                                ResourceLimit {
+                                    resource: Resource::OpenFiles,
+                                    soft: 64,
+                                    hard: 64
+                                },
+                                ResourceLimit {
+                                    resource: Resource::Cpu,
+                                    soft: 3,
+                                    hard: ResourceLimit::UNLIMITED
+                                },
+                            ]
+                        );
+                        Ok(ExecResult::default())
+                    })
+                    .run()
+                    .unwrap();
+            }
+        }
+
+        mod child_setup {
+            use crate::{Command, ExecResult, PreExecHook, ReturnNothing};
+            use proptest::prelude::*;
+            use std::ffi::OsString;
+
+            #[test]
+            fn by_default_nothing_is_changed() {
+                let cmd = Command::new("foo", ReturnNothing);
+                assert_eq!(cmd.umask(), None);
+                assert_eq!(cmd.arg0(), None);
+                assert_eq!(cmd.niceness(), None);
+                #[cfg(target_os = "linux")]
+                assert_eq!(cmd.cpu_affinity(), None);
+                assert!(cmd.pre_exec_hooks().is_empty());
+            }
+
+            #[test]
+            fn pre_exec_hooks_are_visible_in_the_exec_replacement_callback() {
+                let hook = |name| unsafe { PreExecHook::new(name, || Ok(())) };
+                Command::new("foo", ReturnNothing)
+                    .with_pre_exec_hook(hook("first"))
+                    .with_pre_exec_hook(hook("second"))
+                    .with_exec_replacement_callback(|cmd, _| {
+                        let names = cmd
+                            .pre_exec_hooks()
+                            .iter()
+                            .map(PreExecHook::name)
+                            .collect::<Vec<_>>();
+                        assert_eq!(names, ["first", "second"]);
+                        Ok(ExecResult::default())
+                    })
+                    .run()
+                    .unwrap();
+            }
+
+            proptest! {
+                #[test]
+                fn settings_are_visible_in_the_exec_replacement_callback(
+                    umask in proptest::option::of(0u32..0o777),
+                    arg0 in proptest::option::of(any::<OsString>()),
+                    niceness in proptest::option::of(-20i32..20),
+                    cpus in proptest::option::of(proptest::collection::vec(0usize..64, 0..4)),
+                ) {
+                    let _ = &cpus;
+                    let cmd = Command::new("foo", ReturnNothing)
+                        .with_umask(umask)
+                        .with_arg0(arg0.clone())
+                        .with_niceness(niceness);
+                    #[cfg(target_os = "linux")]
+                    let cmd = cmd.with_cpu_affinity(cpus.clone());
+                    cmd.with_exec_replacement_callback(move |cmd, _| {
+                        assert_eq!(cmd.umask(), umask);
+                        assert_eq!(cmd.arg0(), arg0.as_deref());
+                        assert_eq!(cmd.niceness(), niceness);
+                        #[cfg(target_os = "linux")]
+                        assert_eq!(cmd.cpu_affinity(), cpus.as_deref());
+                        Ok(ExecResult::default())
+                    })
+                    .run()
+                    .unwrap();
+                }
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        mod pty {
+            use crate::{Command, ExecResult, PtySize, ReturnNothing};
+            use proptest::prelude::*;
+
+            #[test]
+            fn by_default_no_pty_is_used() {
+                let cmd = Command::new("foo", ReturnNothing);
+                assert_eq!(cmd.pty(), None);
+                assert!(!cmd.owns_process_group());
+            }
+
+            #[test]
+            fn a_pty_implies_a_new_session() {
+                let cmd = Command::new("foo", ReturnNothing).with_pty(Some(PtySize::default()));
+                assert!(cmd.creates_session());
+                assert!(cmd.owns_process_group());
+            }
+
+            proptest! {
+                #[test]
+                fn the_pty_size_is_visible_in_the_exec_replacement_callback(
+                    size in proptest::option::of((any::<u16>(), any::<u16>()))
+                ) {
+                    let size = size.map(|(rows, cols)| PtySize { rows, cols });
+                    Command::new("foo", ReturnNothing)
+                        .with_pty(size)
+                        .with_exec_replacement_callback(move |cmd, _| {
+                            assert_eq!(cmd.pty(), size);
+                            Ok(ExecResult::default())
+                        })
+                        .run()
+                        .unwrap();
+                }
+            }
+        }
+
+        mod inherited_fds {
+            use crate::{Command, ExecResult, ReturnNothing};
+            use std::{
+                fs::File,
+                os::unix::io::{AsRawFd, OwnedFd},
+            };
+
+            fn some_fd() -> OwnedFd {
+                File::open("/dev/null").unwrap().into()
+            }
+
+            #[test]
+            fn by_default_no_additional_fds_are_inherited() {
+                let cmd = Command::new("foo", ReturnNothing);
+                assert!(cmd.inherited_fds().is_empty());
+            }
+
+            #[test]
+            fn requested_fds_are_visible_in_the_exec_replacement_callback() {
+                let (fd3, fd4, other_fd3) = (some_fd(), some_fd(), some_fd());
+                let (raw4, other_raw3) = (fd4.as_raw_fd(), other_fd3.as_raw_fd());
+                Command::new("foo", ReturnNothing)
+                    .with_inherited_fd(3, fd3)
+                    .with_inherited_fd(4, fd4)
+                    .with_inherited_fd(3, other_fd3)
+                    .with_exec_replacement_callback(move |cmd, _| {
+                        let fds = cmd
+                            .inherited_fds()
+                            .iter()
+                            .map(|fd| (fd.target_fd(), fd.fd().as_raw_fd()))
+                            .collect::<Vec<_>>();
+                        assert_eq!(fds, [(4, raw4), (3, other_raw3)]);
+                        Ok(ExecResult::default())
+                    })
+                    .run()
+                    .unwrap();
+            }
+        }
+
+        mod credentials {
+            use crate::{Command, ExecResult, ReturnNothing};
+            use proptest::prelude::*;
+
+            #[test]
+            fn by_default_credentials_are_inherited() {
+                let cmd = Command::new("foo", ReturnNothing);
+                assert_eq!(cmd.uid(), None);
+                assert_eq!(cmd.gid(), None);
+                assert_eq!(cmd.supplementary_groups(), None);
+            }
+
+            proptest! {
+                #[test]
+                fn credentials_are_visible_to_the_exec_replacement_callback(
+                    uid in proptest::option::of(any::<u32>()),
+                    gid in proptest::option::of(any::<u32>()),
+                    groups in proptest::option::of(proptest::collection::vec(any::<u32>(), 0..4))
+                ) {
+                    let expected_groups = groups.clone();
+                    Command::new("foo", ReturnNothing)
+                        .with_uid(uid)
+                        .with_gid(gid)
+                        .with_supplementary_groups(groups)
+                        .with_exec_replacement_callback(move |cmd, _| {
+                            assert_eq!(cmd.uid(), uid);
+                            assert_eq!(cmd.gid(), gid);
+                            assert_eq!(cmd.supplementary_groups(), expected_groups.as_deref());
+                            Ok(ExecResult::default())
+                        })
+                        .run()
+                        .unwrap();
+                }
+            }
+        }
+
+        mod forwarded_signals {
+            use crate::{Command, ExecResult, ReturnNothing};
+            use proptest::prelude::*;
+
+            #[test]
+            fn by_default_no_signals_are_forwarded() {
+                let cmd = Command::new("foo", ReturnNothing);
+                assert!(cmd.forwarded_signals().is_empty());
+            }
+
+            proptest! {
+                #[test]
+                fn forwarded_signals_are_visible_to_the_exec_replacement_callback(
+                    signals in proptest::collection::vec(1..32, 0..4)
+                ) {
+                    let expected = signals.clone();
+                    Command::new("foo", ReturnNothing)
+                        .with_forwarded_signals(signals)
+                        .with_exec_replacement_callback(move |cmd, _| {
+                            assert_eq!(cmd.forwarded_signals(), &*expected);
+                            Ok(ExecResult::default())
+                        })
+                        .run()
+                        .unwrap();
+                }
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        mod parent_death_signal {
+            use crate::{Command, ExecResult, ReturnNothing};
+            use proptest::prelude::*;
+
+            #[test]
+            fn by_default_no_parent_death_signal_is_set() {
+                let cmd = Command::new("foo", ReturnNothing);
+                assert_eq!(cmd.parent_death_signal(), None);
+            }
+
+            proptest! {
+                #[test]
+                fn parent_death_signal_is_visible_to_the_exec_replacement_callback(
+                    signal in proptest::option::of(1..32)
+                ) {
+                    Command::new("foo", ReturnNothing)
+                        .with_parent_death_signal(signal)
+                        .with_exec_replacement_callback(move |cmd, _| {
+                            assert_eq!(cmd.parent_death_signal(), signal);
+                            Ok(ExecResult::default())
+                        })
+                        .run()
+                        .unwrap();
+                }
+            }
+        }
+    }
+}
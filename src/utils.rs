@@ -4,6 +4,9 @@ use ::{
     std::{ffi::OsString, path::PathBuf},
 };
 
+#[cfg(test)]
+use crate::{ExitStatus, OpaqueOsExitStatus};
+
 macro_rules! fused_opt_iter_next {
     ($source:expr, |$name:pat| $code:block) => {{
         let mut drop_iterator = false;
@@ -28,6 +31,19 @@ pub fn opt_arbitrary_path_buf() -> impl Strategy<Value = Option<PathBuf>> {
     ]
 }
 
+#[cfg(test)]
+pub fn arbitrary_exit_status() -> impl Strategy<Value = ExitStatus> {
+    #[cfg(unix)]
+    let os_specific = any::<i32>().prop_map(OpaqueOsExitStatus::from_signal_number);
+    #[cfg(not(unix))]
+    let os_specific = Just(OpaqueOsExitStatus::target_specific_default());
+
+    prop_oneof![
+        any::<i64>().prop_map(ExitStatus::Code),
+        os_specific.prop_map(ExitStatus::OsSpecific)
+    ]
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -0,0 +1,275 @@
+//! Pre-flight validation of commands, see [`Command::validate()`].
+use crate::{Command, EnvChange, ProgramNotFound, UnexpectedExitStatus};
+use std::{
+    ffi::{OsStr, OsString},
+    fmt, io,
+    path::PathBuf,
+};
+use thiserror::Error;
+
+impl<Output, Error> Command<Output, Error>
+where
+    Output: 'static,
+    Error: From<io::Error> + From<UnexpectedExitStatus> + 'static,
+{
+    /// Checks if the command can be spawned, without spawning it.
+    ///
+    /// This checks that:
+    ///
+    /// - the program can be resolved to an executable (see [`Command::resolve_program()`])
+    /// - the [`Command::working_directory_override()`] (if any) exists and is a directory
+    /// - the keys of all env updates are valid, i.e. not empty and without `=` or NUL
+    /// - no value of an env update and no argument contains a NUL byte
+    ///
+    /// All found problems are returned, not just the first one.
+    ///
+    /// Passing validation doesn't guarantee that spawning succeeds, e.g. the program
+    /// could be removed in between or the sub-process might lack permissions.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut problems = Vec::new();
+
+        if let Some(dir) = self.working_directory_override() {
+            match dir.metadata() {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(_) => problems.push(ValidationProblem::WorkingDirectoryNotADirectory(
+                    dir.to_owned(),
+                )),
+                Err(_) => {
+                    problems.push(ValidationProblem::WorkingDirectoryNotFound(dir.to_owned()))
+                }
+            }
+        }
+
+        if let Err(err) = self.resolve_program() {
+            problems.push(ValidationProblem::ProgramNotFound(err));
+        }
+
+        for (index, (argument, redacted)) in self
+            .arguments()
+            .iter()
+            .zip(self.redacted_arguments())
+            .enumerate()
+        {
+            if contains_nul(argument) {
+                problems.push(ValidationProblem::ArgumentContainsNul {
+                    index,
+                    argument: redacted.to_owned(),
+                });
+            }
+        }
+
+        let mut env_updates = self.env_updates().iter().collect::<Vec<_>>();
+        env_updates.sort_by_key(|(key, _)| *key);
+        for (key, change) in env_updates {
+            if key.is_empty() || key.as_encoded_bytes().contains(&b'=') || contains_nul(key) {
+                problems.push(ValidationProblem::InvalidEnvKey(key.clone()));
+            }
+            match change {
+                EnvChange::Set(value) | EnvChange::Prepend(value) | EnvChange::Append(value)
+                    if contains_nul(value) =>
+                {
+                    problems.push(ValidationProblem::EnvValueContainsNul(key.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError { problems })
+        }
+    }
+
+    /// Returns true if [`Command::validate()`] is run before spawning the sub-process.
+    ///
+    /// By default this is `false`.
+    pub fn validate_before_spawn(&self) -> bool {
+        self.validate_before_spawn
+    }
+
+    /// Returns this command with validation before spawning enabled or disabled.
+    ///
+    /// If enabled and [`Command::validate()`] fails the sub-process is not spawned and
+    /// the run fails with an `io::Error` of kind `InvalidInput` wrapping the
+    /// [`ValidationError`]. Errors of this crate like [`CommandExecutionError`] convert such
+    /// `io::Error`s into a dedicated variant.
+    ///
+    /// Exec replacement callbacks (e.g. mocks) are not affected by this setting.
+    ///
+    /// [`CommandExecutionError`]: crate::CommandExecutionError
+    pub fn with_validate_before_spawn(mut self, validate: bool) -> Self {
+        self.validate_before_spawn = validate;
+        self
+    }
+}
+
+/// A problem found by [`Command::validate()`].
+#[derive(Debug, Error)]
+pub enum ValidationProblem {
+    /// The program could not be resolved.
+    #[error(transparent)]
+    ProgramNotFound(ProgramNotFound),
+
+    /// The working directory override does not exist.
+    #[error("Working directory {0:?} does not exist")]
+    WorkingDirectoryNotFound(PathBuf),
+
+    /// The working directory override is not a directory.
+    #[error("Working directory {0:?} is not a directory")]
+    WorkingDirectoryNotADirectory(PathBuf),
+
+    /// The key of an env update is empty or contains `=` or NUL.
+    #[error("Invalid env variable name {0:?}")]
+    InvalidEnvKey(OsString),
+
+    /// The value of the env update for given key contains NUL.
+    #[error("Value of env variable {0:?} contains a NUL byte")]
+    EnvValueContainsNul(OsString),
+
+    /// The argument at given index contains NUL.
+    ///
+    /// Secret arguments are replaced with `***`.
+    #[error("Argument {index} contains a NUL byte: {argument:?}")]
+    ArgumentContainsNul {
+        /// The index of the argument (not counting the program).
+        index: usize,
+        /// The argument.
+        argument: OsString,
+    },
+}
+
+/// The problems found by [`Command::validate()`].
+///
+/// The `Display` implementation lists all problems.
+#[derive(Debug, Error)]
+pub struct ValidationError {
+    problems: Vec<ValidationProblem>,
+}
+
+impl ValidationError {
+    /// The found problems, this is never empty.
+    pub fn problems(&self) -> &[ValidationProblem] {
+        &self.problems
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, fter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fter, "Invalid command:")?;
+        for problem in &self.problems {
+            write!(fter, "\n- {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ValidationError> for io::Error {
+    fn from(err: ValidationError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+fn contains_nul(value: &OsStr) -> bool {
+    value.as_encoded_bytes().contains(&0)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod validate {
+        use crate::{
+            Command, CommandExecutionError, EnvChange, ExecResult, ReturnNothing, ValidationProblem,
+        };
+        use std::env;
+
+        #[test]
+        fn valid_commands_pass() {
+            let cmd = Command::new(env::current_exe().unwrap(), ReturnNothing)
+                .with_argument("foo")
+                .with_env_update("FOO", "bar")
+                .with_working_directory_override(Some(env::temp_dir()));
+            cmd.validate().unwrap();
+        }
+
+        #[test]
+        fn all_problems_are_reported() {
+            let file = env::current_exe().unwrap();
+            let cmd = Command::new("/does/not/exist", ReturnNothing)
+                .with_arguments(vec!["ok", "a\0b"])
+                .with_env_update("A=B", "x")
+                .with_env_update("", "x")
+                .with_env_update("NUL", EnvChange::Prepend("a\0b".into()))
+                .with_working_directory_override(Some(&file));
+
+            let err = cmd.validate().unwrap_err();
+            let problems = err.problems();
+
+            assert_eq!(problems.len(), 6, "{:?}", problems);
+            assert!(
+                matches!(&problems[0], ValidationProblem::WorkingDirectoryNotADirectory(dir) if *dir == file)
+            );
+            assert!(matches!(
+                &problems[1],
+                ValidationProblem::ProgramNotFound(_)
+            ));
+            assert!(matches!(
+                &problems[2],
+                ValidationProblem::ArgumentContainsNul { index: 1, argument } if argument == "a\0b"
+            ));
+            assert!(
+                matches!(&problems[3], ValidationProblem::InvalidEnvKey(key) if key.is_empty())
+            );
+            assert!(matches!(&problems[4], ValidationProblem::InvalidEnvKey(key) if key == "A=B"));
+            assert!(
+                matches!(&problems[5], ValidationProblem::EnvValueContainsNul(key) if key == "NUL")
+            );
+        }
+
+        #[test]
+        fn missing_working_directories_are_reported() {
+            let err = Command::new(env::current_exe().unwrap(), ReturnNothing)
+                .with_working_directory_override(Some("/does/not/exist"))
+                .validate()
+                .unwrap_err();
+
+            assert_eq!(
+                err.to_string(),
+                "Invalid command:\n- Working directory \"/does/not/exist\" does not exist"
+            );
+        }
+
+        #[test]
+        fn by_default_commands_are_not_validated_before_spawn() {
+            let cmd = Command::new("foo", ReturnNothing);
+            assert!(!cmd.validate_before_spawn());
+            assert!(cmd.with_validate_before_spawn(true).validate_before_spawn());
+        }
+
+        #[test]
+        fn validation_before_spawn_fails_the_run() {
+            let err = Command::new(env::current_exe().unwrap(), ReturnNothing)
+                .with_argument("a\0b")
+                .with_validate_before_spawn(true)
+                .run()
+                .unwrap_err();
+
+            match err {
+                CommandExecutionError::Validation(err) => {
+                    assert_eq!(err.problems().len(), 1);
+                }
+                err => panic!("unexpected error: {:?}", err),
+            }
+        }
+
+        #[test]
+        fn validation_is_not_run_with_exec_replacement_callbacks() {
+            Command::new("/does/not/exist", ReturnNothing)
+                .with_validate_before_spawn(true)
+                .with_exec_replacement_callback(|_, _| Ok(ExecResult::default()))
+                .run()
+                .unwrap();
+        }
+    }
+}
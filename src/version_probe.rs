@@ -0,0 +1,624 @@
+//! Probing the version of programs, see [`VersionProbe`].
+use crate::{CapturedStdoutAndErr, Command, CommandExecutionError, ReturnStdoutAndErr};
+use regex::Regex;
+use std::{
+    cmp::Ordering, collections::HashMap, env, ffi::OsString, fmt, path::PathBuf, str::FromStr,
+    sync::Mutex,
+};
+use thiserror::Error;
+
+/// The regex used by [`VersionProbe::new()`], matching e.g. `2.30` or `2.30.1`.
+pub const DEFAULT_VERSION_REGEX: &str = r"(\d+)\.(\d+)(?:\.(\d+))?";
+
+/// Cache key: resolved program path, arguments, working directory and version regex.
+type CacheKey = (PathBuf, Vec<OsString>, PathBuf, String);
+
+static VERSION_CACHE: Mutex<Option<HashMap<CacheKey, Version>>> = Mutex::new(None);
+
+/// Runs a command to find out the version of a program, e.g. `git --version`.
+///
+/// The version is extracted from the captured stdout (or if not found there stderr)
+/// using a regex. Probed versions are cached per resolved program path (see
+/// [`Command::resolve_program()`]), arguments, working directory and regex, so probing
+/// the same program multiple times only runs it once. The working directory is part of
+/// the key as version manager shims (e.g. of pyenv) pick the version based on it.
+/// Commands with env updates, a not fully inherited env, changed credentials or an
+/// exec replacement callback are neither cached nor answered from the cache.
+///
+/// ```no_run
+/// use mapped_command::VersionProbe;
+///
+/// let version = VersionProbe::new("git").requires(">=2.30")?;
+/// if version >= "2.38".parse()? {
+///     // use `git worktree add --orphan`
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct VersionProbe {
+    command: Command<CapturedStdoutAndErr, CommandExecutionError>,
+    regex: Regex,
+}
+
+impl VersionProbe {
+    /// Creates a probe running `program --version` and using the [`DEFAULT_VERSION_REGEX`].
+    pub fn new(program: impl Into<OsString>) -> Self {
+        VersionProbe::from_command(
+            Command::new(program, ReturnStdoutAndErr).with_argument("--version"),
+        )
+    }
+
+    /// Creates a probe running given command and using the [`DEFAULT_VERSION_REGEX`].
+    ///
+    /// This allows configuring the command, e.g. using `-V` instead of `--version` or
+    /// setting env variables.
+    pub fn from_command(command: Command<CapturedStdoutAndErr, CommandExecutionError>) -> Self {
+        VersionProbe {
+            command,
+            regex: Regex::new(DEFAULT_VERSION_REGEX).unwrap(),
+        }
+    }
+
+    /// Returns the command which is run to probe the version.
+    pub fn command(&self) -> &Command<CapturedStdoutAndErr, CommandExecutionError> {
+        &self.command
+    }
+
+    /// Returns the regex used to extract the version.
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// Returns this probe with given regex used to extract the version.
+    ///
+    /// The first match is used. Capture group 1 must match the major version, the
+    /// optional capture groups 2 and 3 the minor and patch version (missing ones are 0).
+    pub fn with_regex(mut self, regex: Regex) -> Self {
+        self.regex = regex;
+        self
+    }
+
+    /// Returns the version of the program, running the command if it's not cached.
+    ///
+    /// Fails if the command fails or no version was found in its output.
+    pub fn probe(self) -> Result<Version, VersionProbeError> {
+        let VersionProbe { command, regex } = self;
+        // Mocked runs and runs with a changed env (e.g. `PYENV_VERSION`) might not
+        // report the version of the installed program, so they are not cached.
+        #[cfg(unix)]
+        let switches_credentials = command.switches_credentials();
+        #[cfg(not(unix))]
+        let switches_credentials = false;
+        let cacheable = !command.has_exec_replacement_callback()
+            && command.env_updates().is_empty()
+            && command.inherit_env()
+            && command.env_inherit_filter().is_none()
+            && !switches_credentials;
+        let cache_key = if cacheable {
+            let working_directory =
+                env::current_dir()
+                    .ok()
+                    .map(|current| match command.working_directory_override() {
+                        Some(dir) => current.join(dir),
+                        None => current,
+                    });
+            command.resolve_program().ok().zip(working_directory).map(
+                |(path, working_directory)| {
+                    (
+                        path,
+                        command.arguments().to_owned(),
+                        working_directory,
+                        regex.as_str().to_owned(),
+                    )
+                },
+            )
+        } else {
+            None
+        };
+        if let Some(cache_key) = &cache_key {
+            let cache = VERSION_CACHE
+                .lock()
+                .unwrap_or_else(|poison| poison.into_inner());
+            if let Some(version) = cache.as_ref().and_then(|cache| cache.get(cache_key)) {
+                return Ok(*version);
+            }
+        }
+
+        let program = command.program().to_owned();
+        let output = command.run()?;
+        let version = [&output.stdout, &output.stderr]
+            .iter()
+            .find_map(|output| extract_version(&regex, &String::from_utf8_lossy(output)))
+            .ok_or_else(|| VersionProbeError::NoVersionFound {
+                program,
+                output: String::from_utf8_lossy(&output.stdout).into_owned(),
+            })?;
+
+        if let Some(cache_key) = cache_key {
+            VERSION_CACHE
+                .lock()
+                .unwrap_or_else(|poison| poison.into_inner())
+                .get_or_insert_with(HashMap::new)
+                .insert(cache_key, version);
+        }
+        Ok(version)
+    }
+
+    /// Returns the version of the program if it satisfies given [`VersionRequirement`].
+    ///
+    /// The requirement is parsed with [`VersionRequirement::from_str()`], e.g. `">=2.30"`.
+    pub fn requires(self, requirement: &str) -> Result<Version, VersionProbeError> {
+        let requirement = requirement.parse::<VersionRequirement>()?;
+        let program = self.command.program().to_owned();
+        let version = self.probe()?;
+        if requirement.matches(&version) {
+            Ok(version)
+        } else {
+            Err(VersionProbeError::Unsatisfied {
+                program,
+                version,
+                requirement,
+            })
+        }
+    }
+}
+
+/// Removes all versions cached by [`VersionProbe::probe()`].
+///
+/// This is useful if a program was updated while the process is running.
+pub fn clear_version_cache() {
+    *VERSION_CACHE
+        .lock()
+        .unwrap_or_else(|poison| poison.into_inner()) = None;
+}
+
+fn extract_version(regex: &Regex, output: &str) -> Option<Version> {
+    let captures = regex.captures(output)?;
+    let part = |idx| match captures.get(idx) {
+        Some(part) => part.as_str().parse().ok(),
+        None => Some(0),
+    };
+    Some(Version {
+        major: captures.get(1)?.as_str().parse().ok()?,
+        minor: part(2)?,
+        patch: part(3)?,
+    })
+}
+
+/// Error returned by [`VersionProbe::probe()`] and [`VersionProbe::requires()`].
+#[derive(Debug, Error)]
+pub enum VersionProbeError {
+    /// Running the command failed.
+    #[error(transparent)]
+    Command(#[from] CommandExecutionError),
+
+    /// The version regex didn't match the output of the command.
+    #[error("No version found in the output of {program:?}: {output:?}")]
+    NoVersionFound {
+        /// The probed program.
+        program: OsString,
+        /// The captured stdout of the command.
+        output: String,
+    },
+
+    /// The requirement passed to [`VersionProbe::requires()`] is invalid.
+    #[error(transparent)]
+    InvalidRequirement(#[from] InvalidVersionRequirement),
+
+    /// The version doesn't satisfy the requirement.
+    #[error("{program:?} has version {version} but {requirement} is required")]
+    Unsatisfied {
+        /// The probed program.
+        program: OsString,
+        /// The version of the program.
+        version: Version,
+        /// The requirement it doesn't satisfy.
+        requirement: VersionRequirement,
+    },
+}
+
+/// A `major.minor.patch` version.
+///
+/// Can be parsed from strings like `2`, `2.30` or `2.30.1` (missing parts are 0).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    /// The major version.
+    pub major: u64,
+    /// The minor version.
+    pub minor: u64,
+    /// The patch version.
+    pub patch: u64,
+}
+
+impl Version {
+    /// Creates a new version.
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    fn parts(&self) -> [u64; 3] {
+        [self.major, self.minor, self.patch]
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, fter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fter, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for Version {
+    type Err = InvalidVersionRequirement;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (parts, _) = parse_version_parts(input)?;
+        Ok(Version::new(parts[0], parts[1], parts[2]))
+    }
+}
+
+/// A requirement on a [`Version`], e.g. `>=2.30` or `>=2.30, <3`.
+///
+/// A requirement is a comma separated list of comparisons which all must match. The
+/// supported operators are `>=`, `>`, `<=`, `<` and `=` (which can be omitted). The
+/// comparisons only consider the given parts of the version, e.g. `=2.30` matches
+/// `2.30.1` and `>2.30` doesn't match `2.30.1`, but `2.31.0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionRequirement {
+    comparisons: Vec<Comparison>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comparison {
+    operator: &'static str,
+    parts: [u64; 3],
+    len: usize,
+}
+
+impl VersionRequirement {
+    /// Returns true if given version satisfies this requirement.
+    pub fn matches(&self, version: &Version) -> bool {
+        self.comparisons.iter().all(|comparison| {
+            let ordering =
+                version.parts()[..comparison.len].cmp(&comparison.parts[..comparison.len]);
+            match comparison.operator {
+                ">=" => ordering != Ordering::Less,
+                ">" => ordering == Ordering::Greater,
+                "<=" => ordering != Ordering::Greater,
+                "<" => ordering == Ordering::Less,
+                _ => ordering == Ordering::Equal,
+            }
+        })
+    }
+}
+
+impl fmt::Display for VersionRequirement {
+    fn fmt(&self, fter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, comparison) in self.comparisons.iter().enumerate() {
+            if idx > 0 {
+                write!(fter, ", ")?;
+            }
+            write!(fter, "{}", comparison.operator)?;
+            for (idx, part) in comparison.parts[..comparison.len].iter().enumerate() {
+                if idx > 0 {
+                    write!(fter, ".")?;
+                }
+                write!(fter, "{}", part)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for VersionRequirement {
+    type Err = InvalidVersionRequirement;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let comparisons = input
+            .split(',')
+            .map(|comparison| {
+                let comparison = comparison.trim();
+                let operator = [">=", "<=", ">", "<", "="]
+                    .iter()
+                    .find(|operator| comparison.starts_with(*operator))
+                    .copied()
+                    .unwrap_or("");
+                let (parts, len) = parse_version_parts(comparison[operator.len()..].trim())
+                    .map_err(|err| InvalidVersionRequirement {
+                        input: input.to_owned(),
+                        reason: err.reason,
+                    })?;
+                Ok(Comparison {
+                    operator: if operator.is_empty() { "=" } else { operator },
+                    parts,
+                    len,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(VersionRequirement { comparisons })
+    }
+}
+
+/// Parses 1 to 3 `.` separated numbers, returns the parts (filled up with 0) and their number.
+fn parse_version_parts(input: &str) -> Result<([u64; 3], usize), InvalidVersionRequirement> {
+    let invalid = |reason: &str| InvalidVersionRequirement {
+        input: input.to_owned(),
+        reason: reason.to_owned(),
+    };
+    let mut parts = [0; 3];
+    let mut len = 0;
+    for part in input.split('.') {
+        if len == parts.len() {
+            return Err(invalid("more than 3 version parts"));
+        }
+        parts[len] = part
+            .parse()
+            .map_err(|_| invalid(&format!("{:?} is not a version number", part)))?;
+        len += 1;
+    }
+    Ok((parts, len))
+}
+
+/// A [`Version`] or [`VersionRequirement`] could not be parsed.
+#[derive(Debug, Error)]
+#[error("Invalid version (requirement) {input:?}: {reason}")]
+pub struct InvalidVersionRequirement {
+    input: String,
+    reason: String,
+}
+
+impl InvalidVersionRequirement {
+    /// The string which could not be parsed.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+
+    mod VersionProbe {
+        use crate::{
+            Command, CommandExecutionError, ExecResult, ReturnStdoutAndErr, VersionProbe,
+            VersionProbeError,
+        };
+        use regex::Regex;
+
+        fn fake_tool(stdout: &'static str, stderr: &'static str) -> VersionProbe {
+            VersionProbe::from_command(
+                Command::new("mapped-command-fake-tool", ReturnStdoutAndErr)
+                    .with_argument("--version")
+                    .with_exec_replacement_callback(move |_, _| {
+                        Ok(ExecResult {
+                            exit_status: 0.into(),
+                            stdout: Some(stdout.into()),
+                            stderr: Some(stderr.into()),
+                            ..Default::default()
+                        })
+                    }),
+            )
+        }
+
+        #[test]
+        fn new_runs_the_program_with_version_argument() {
+            let probe = VersionProbe::new("git");
+            assert_eq!(probe.command().arguments(), &["--version"]);
+            assert_eq!(probe.regex().as_str(), super::super::DEFAULT_VERSION_REGEX);
+        }
+
+        #[test]
+        fn versions_are_extracted_from_stdout_or_stderr() {
+            let version = fake_tool("git version 2.34.1\n", "").probe().unwrap();
+            assert_eq!(version.to_string(), "2.34.1");
+
+            let version = fake_tool("", "Python 2.7\n").probe().unwrap();
+            assert_eq!(version.to_string(), "2.7.0");
+        }
+
+        #[test]
+        fn the_regex_can_be_configured() {
+            let version = fake_tool("tool 1.0 (build 10.2)", "")
+                .with_regex(Regex::new(r"build (\d+)\.(\d+)").unwrap())
+                .probe()
+                .unwrap();
+            assert_eq!(version.to_string(), "10.2.0");
+        }
+
+        #[test]
+        fn missing_versions_are_reported() {
+            let err = fake_tool("no version here", "").probe().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "No version found in the output of \"mapped-command-fake-tool\": \"no version here\""
+            );
+        }
+
+        #[test]
+        fn requirements_are_checked() {
+            let version = fake_tool("git version 2.34.1", "")
+                .requires(">=2.30")
+                .unwrap();
+            assert_eq!(version.to_string(), "2.34.1");
+
+            let err = fake_tool("git version 2.25.0", "")
+                .requires(">=2.30, <3")
+                .unwrap_err();
+            assert!(matches!(err, VersionProbeError::Unsatisfied { .. }));
+            assert_eq!(
+                err.to_string(),
+                "\"mapped-command-fake-tool\" has version 2.25.0 but >=2.30, <3 is required"
+            );
+
+            let err = fake_tool("git version 2.25.0", "")
+                .requires(">=two")
+                .unwrap_err();
+            assert!(matches!(err, VersionProbeError::InvalidRequirement(_)));
+        }
+
+        #[test]
+        fn command_failures_are_reported() {
+            let err = VersionProbe::new("mapped-command-missing-program")
+                .probe()
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                VersionProbeError::Command(CommandExecutionError::ProgramNotFound(_))
+            ));
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn only_real_runs_with_the_inherited_env_are_cached() {
+            use std::{env, fs, os::unix::fs::PermissionsExt};
+
+            let dir =
+                env::temp_dir().join(format!("mapped-command-{}-version", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let calls = dir.join("calls");
+            let tool = dir.join("tool");
+            fs::write(
+                &tool,
+                format!(
+                    "#!/bin/sh\necho called >> '{}'\necho 'tool 3.1.4'\n",
+                    calls.display()
+                ),
+            )
+            .unwrap();
+            fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+
+            let mocked = VersionProbe::from_command(
+                Command::new(&tool, ReturnStdoutAndErr)
+                    .with_argument("--version")
+                    .with_exec_replacement_callback(|_, _| {
+                        Ok(ExecResult {
+                            exit_status: 0.into(),
+                            stdout: Some(b"tool 9.9.9".to_vec()),
+                            stderr: Some(Vec::new()),
+                            ..Default::default()
+                        })
+                    }),
+            )
+            .probe();
+            let first = VersionProbe::new(&tool).probe();
+            let second = VersionProbe::new(&tool).probe();
+            // Env updates might change the version (e.g. `PYENV_VERSION`), so it's not cached.
+            let with_env = VersionProbe::from_command(
+                Command::new(&tool, ReturnStdoutAndErr)
+                    .with_argument("--version")
+                    .with_env_update("TOOL_VERSION", "2"),
+            )
+            .probe();
+            let calls = fs::read_to_string(&calls);
+            fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!(mocked.unwrap().to_string(), "9.9.9");
+            assert_eq!(first.unwrap().to_string(), "3.1.4");
+            assert_eq!(second.unwrap().to_string(), "3.1.4");
+            assert_eq!(with_env.unwrap().to_string(), "3.1.4");
+            assert_eq!(calls.unwrap(), "called\ncalled\n");
+        }
+
+        #[cfg(unix)]
+        #[test]
+        fn versions_are_cached_per_working_directory_and_not_with_credentials() {
+            use std::{env, fs, os::unix::fs::PermissionsExt};
+
+            let dir = env::temp_dir().join(format!(
+                "mapped-command-{}-version-per-dir",
+                std::process::id()
+            ));
+            fs::create_dir_all(dir.join("a")).unwrap();
+            fs::create_dir_all(dir.join("b")).unwrap();
+            let calls = dir.join("calls");
+            let tool = dir.join("tool");
+            // Like a version manager shim, the version depends on the working directory.
+            fs::write(
+                &tool,
+                format!(
+                    "#!/bin/sh\necho called >> '{}'\ncase \"$PWD\" in */a) echo 'tool 1.0';; *) echo 'tool 2.0';; esac\n",
+                    calls.display()
+                ),
+            )
+            .unwrap();
+            fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+
+            let probe = |sub_dir: &str| {
+                VersionProbe::from_command(
+                    Command::new(&tool, ReturnStdoutAndErr)
+                        .with_working_directory_override(Some(dir.join(sub_dir))),
+                )
+                .probe()
+                .map(|version| version.to_string())
+            };
+            let in_a = probe("a");
+            let in_b = probe("b");
+            let in_a_again = probe("a");
+            let with_uid = || {
+                VersionProbe::from_command(
+                    Command::new(&tool, ReturnStdoutAndErr)
+                        .with_working_directory_override(Some(dir.join("a")))
+                        .with_uid(Some(unsafe { libc::getuid() })),
+                )
+                .probe()
+            };
+            let with_uid = [with_uid(), with_uid()];
+            let calls = fs::read_to_string(&calls);
+            fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!(in_a.unwrap(), "1.0.0");
+            assert_eq!(in_b.unwrap(), "2.0.0");
+            assert_eq!(in_a_again.unwrap(), "1.0.0");
+            for version in with_uid {
+                assert_eq!(version.unwrap().to_string(), "1.0.0");
+            }
+            assert_eq!(calls.unwrap(), "called\n".repeat(4));
+        }
+    }
+
+    mod VersionRequirement {
+        use crate::{Version, VersionRequirement};
+
+        fn matches(requirement: &str, version: &str) -> bool {
+            requirement
+                .parse::<VersionRequirement>()
+                .unwrap()
+                .matches(&version.parse::<Version>().unwrap())
+        }
+
+        #[test]
+        fn comparisons_only_consider_the_given_parts() {
+            assert!(matches(">=2.30", "2.30.0"));
+            assert!(matches(">=2.30", "3.0.0"));
+            assert!(!matches(">=2.30", "2.29.9"));
+            assert!(matches("=2.30", "2.30.7"));
+            assert!(matches("2", "2.30.7"));
+            assert!(!matches(">2.30", "2.30.1"));
+            assert!(matches(">2.30", "2.31"));
+            assert!(matches("<=2.30", "2.30.1"));
+            assert!(!matches("<2.30", "2.30.1"));
+            assert!(matches(">= 1.2, < 2", "1.9.9"));
+            assert!(!matches(">= 1.2, < 2", "2.0.0"));
+        }
+
+        #[test]
+        fn requirements_are_displayed_normalized() {
+            let requirement = " >= 1.2,<2, 3.1.4".parse::<VersionRequirement>().unwrap();
+            assert_eq!(requirement.to_string(), ">=1.2, <2, =3.1.4");
+        }
+
+        #[test]
+        fn invalid_requirements_are_rejected() {
+            let err = ">=1.2.3.4".parse::<VersionRequirement>().unwrap_err();
+            assert_eq!(err.input(), ">=1.2.3.4");
+            assert_eq!(
+                err.to_string(),
+                "Invalid version (requirement) \">=1.2.3.4\": more than 3 version parts"
+            );
+            assert!("".parse::<VersionRequirement>().is_err());
+            assert!(">=1.x".parse::<VersionRequirement>().is_err());
+        }
+    }
+}